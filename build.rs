@@ -0,0 +1,19 @@
+use std::env;
+
+// ffmpeg-sys-next 通过 links 元数据告诉依赖它的包编译时用的 FFmpeg 版本（DEP_FFMPEG_FFMPEG_6_1=true 等），
+// 转成 ffmpeg_6_1 这样的 cfg，只有新版本才有的字段和函数据此选择
+fn main() {
+    for (name, value) in env::vars() {
+        let Some(version) = name.strip_prefix("DEP_FFMPEG_") else {
+            continue;
+        };
+        let version = version.to_lowercase();
+        if !version.starts_with("ffmpeg_") {
+            continue;
+        }
+        println!("cargo:rustc-check-cfg=cfg({})", version);
+        if value == "true" {
+            println!("cargo:rustc-cfg={}", version);
+        }
+    }
+}
@@ -1,91 +1,287 @@
 extern crate ffmpeg_next as ffmpeg;
 
+use std::marker::PhantomData;
+use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 use bytemuck::Pod;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::SizedSample;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{FromSample, Sample, SizedSample};
 
 use futures::future::OptionFuture;
 use futures::FutureExt;
 use ringbuf::ring_buffer::{RbRef, RbWrite};
-use ringbuf::HeapRb;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
 use std::future::Future;
 
-use crate::player::ControlCommand;
+use crate::audio_device::{choose_output_config, find_output_device, AudioDeviceSelector};
+use crate::audio_sink::{AudioOutput, ClockedSink};
+use crate::clock::{MasterClock, SyncMode};
+use crate::error::{ErrorCallback, PlayerError};
+use crate::player::{
+    queue_fill_percent, send_event, ControlCommand, DecoderTransition, PacketMessage, PlayerEvent, PreparedDecoder,
+};
+
+// 音量从 0 变到 1 所用的时间，渐变避免调整音量时出现爆音
+const VOLUME_RAMP_SECONDS: f32 = 0.02;
+// 最大音量，2.0 即 200%
+pub const MAX_VOLUME: f32 = 2.0;
+// 环形缓冲区能容纳的时长，容量按输出的采样率和声道数换算
+const RING_BUFFER_SECONDS: f64 = 0.1;
+// 以下几个常量与 ffplay 相同：音频时钟和主时钟相差超过这个秒数就不再修正
+const AUDIO_NOSYNC_THRESHOLD: f64 = 10.0;
+// 差值取最近这么多帧的加权平均
+const AUDIO_DIFF_AVG_NB: u32 = 20;
+// 每帧采样数最多增减的百分比
+const SAMPLE_CORRECTION_PERCENT_MAX: usize = 10;
+// 平均差值小于环形缓冲区时长时不修正，缓冲区本身就有这么大的误差
+const AUDIO_DIFF_THRESHOLD: f64 = RING_BUFFER_SECONDS;
+
+// 音量和静音设置，Player 写入，输出回调读取
+pub struct VolumeControl {
+    // f32 的位模式
+    volume: AtomicU32,
+    muted: AtomicBool,
+}
+
+impl VolumeControl {
+    pub fn new(volume: f32, muted: bool) -> Self {
+        Self {
+            volume: AtomicU32::new(clamp_volume(volume).to_bits()),
+            muted: AtomicBool::new(muted),
+        }
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.volume.store(clamp_volume(volume).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    // 回调中实际要达到的增益
+    fn target_gain(&self) -> f32 {
+        if self.muted.load(Ordering::Relaxed) {
+            0.0
+        } else {
+            f32::from_bits(self.volume.load(Ordering::Relaxed))
+        }
+    }
+}
+
+// 把音量限制在 0.0..=MAX_VOLUME，NaN 按静音处理
+pub(crate) fn clamp_volume(volume: f32) -> f32 {
+    if volume.is_nan() {
+        0.0
+    } else {
+        volume.clamp(0.0, MAX_VOLUME)
+    }
+}
+
+// 音频滤镜设置，Player 写入，音频线程在每一帧前检查版本号决定是否重建滤镜图
+#[derive(Default)]
+pub struct AudioFilterSettings {
+    // libavfilter 滤镜描述，如 "highpass=f=100,loudnorm"，空字符串表示不经过滤镜
+    description: Mutex<String>,
+    generation: AtomicU64,
+}
+
+impl AudioFilterSettings {
+    pub fn new(description: &str) -> Self {
+        Self { description: Mutex::new(description.trim().to_string()), generation: AtomicU64::new(0) }
+    }
+
+    pub fn set_description(&self, description: &str) {
+        *self.description.lock().unwrap() = description.trim().to_string();
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn description(&self) -> String {
+        self.description.lock().unwrap().clone()
+    }
+}
+
+// 下混时的矩阵编码方式，对应 swresample 的 matrix_encoding 选项
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatrixEncoding {
+    #[default]
+    None,
+    // 兼容杜比环绕声 (Dolby Surround) 的立体声
+    Dolby,
+    // 兼容杜比定向逻辑 II (Dolby Pro Logic II) 的立体声
+    DolbyProLogic2,
+}
+
+impl MatrixEncoding {
+    fn option_value(self) -> &'static str {
+        match self {
+            MatrixEncoding::None => "none",
+            MatrixEncoding::Dolby => "dolby",
+            MatrixEncoding::DolbyProLogic2 => "dplii",
+        }
+    }
+}
+
+// 源声道多于输出设备时的下混参数，电平为线性系数
+#[derive(Clone, Copy, Debug)]
+pub struct DownmixOptions {
+    pub center_mix_level: f64,
+    pub surround_mix_level: f64,
+    pub lfe_mix_level: f64,
+    pub matrix_encoding: MatrixEncoding,
+}
+
+impl Default for DownmixOptions {
+    // 与 swresample 的默认值一致：中置和环绕 -3dB，丢弃低音声道
+    fn default() -> Self {
+        Self {
+            center_mix_level: std::f64::consts::FRAC_1_SQRT_2,
+            surround_mix_level: std::f64::consts::FRAC_1_SQRT_2,
+            lfe_mix_level: 0.0,
+            matrix_encoding: MatrixEncoding::None,
+        }
+    }
+}
+
+impl DownmixOptions {
+    fn resampler_options(&self) -> ffmpeg::Dictionary<'static> {
+        let mut options = ffmpeg::Dictionary::new();
+        options.set("center_mix_level", &self.center_mix_level.to_string());
+        options.set("surround_mix_level", &self.surround_mix_level.to_string());
+        options.set("lfe_mix_level", &self.lfe_mix_level.to_string());
+        options.set("matrix_encoding", self.matrix_encoding.option_value());
+        options
+    }
+}
+
+// 输出设备声道数对应的标准声道布局
+fn output_channel_layout(channels: u16) -> Option<ffmpeg::util::channel_layout::ChannelLayout> {
+    use ffmpeg::util::channel_layout::ChannelLayout;
+    match channels {
+        1 => Some(ChannelLayout::MONO),
+        2 => Some(ChannelLayout::STEREO),
+        3 => Some(ChannelLayout::SURROUND),
+        4 => Some(ChannelLayout::QUAD),
+        5 => Some(ChannelLayout::_5POINT0),
+        6 => Some(ChannelLayout::_5POINT1),
+        7 => Some(ChannelLayout::_6POINT1),
+        8 => Some(ChannelLayout::_7POINT1),
+        _ => None,
+    }
+}
+
+// 音频线程的输出设置，以及在先后加载的文件之间共用的状态
+pub(crate) struct AudioPlaybackConfig {
+    pub(crate) device_selector: AudioDeviceSelector,
+    pub(crate) volume_control: Arc<VolumeControl>,
+    pub(crate) filter_settings: Arc<AudioFilterSettings>,
+    pub(crate) downmix: DownmixOptions,
+    pub(crate) output_slot: Arc<AudioOutputSlot>,
+    pub(crate) error_callback: ErrorCallback,
+}
 
 pub struct AudioPlaybackThread {
     control_sender: smol::channel::Sender<ControlCommand>,
-    packet_sender: smol::channel::Sender<ffmpeg::codec::packet::packet::Packet>,
+    packet_sender: smol::channel::Sender<PacketMessage>,
+    // 保留一个接收端，跳转时由解复用线程直接清空队列
+    packet_receiver: smol::channel::Receiver<PacketMessage>,
+    // 收到结束标记并把缓冲区里的采样都播放完后置位
+    finished: Arc<AtomicBool>,
     receiver_thread: Option<std::thread::JoinHandle<()>>,
 }
 
 impl AudioPlaybackThread {
-    pub fn start(stream: &ffmpeg::format::stream::Stream) -> Result<Self, anyhow::Error> {
+    pub(crate) fn start(
+        stream: &ffmpeg::format::stream::Stream,
+        master_clock: Arc<MasterClock>,
+        output: &AudioOutput,
+        config: AudioPlaybackConfig,
+        event_sender: smol::channel::Sender<PlayerEvent>,
+    ) -> Result<Self, PlayerError> {
         println!("音频线程启动 - 流信息: {}", stream.duration());
+        let AudioPlaybackConfig {
+            device_selector,
+            volume_control,
+            filter_settings,
+            downmix,
+            output_slot,
+            error_callback,
+        } = config;
 
         let (control_sender, control_receiver) = smol::channel::unbounded();
 
         let (packet_sender, packet_receiver) = smol::channel::bounded(128);
 
-        let decoder_context = ffmpeg::codec::Context::from_parameters(stream.parameters())?;
-        let packet_decoder = decoder_context.decoder().audio()?;
+        let packet_decoder = open_decoder(stream)?;
 
         println!("音频解码器初始化完成 - 格式: {:?}", packet_decoder.format());
 
-        let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .expect("no output device available");
-        println!("音频输出设备: {:?}", device.name());
+        let time_base = stream.time_base();
 
-        let config = device.default_output_config().unwrap();
-        println!(
-            "音频输出配置 - 采样率: {}, 通道: {}, 格式: {:?}",
-            config.sample_rate().0,
-            config.channels(),
-            config.sample_format()
-        );
+        // 上一个文件留下的输出还能用就接着用，不重新打开设备
+        let active_output = match output_slot.take() {
+            Some(active_output) if !active_output.has_failed() => {
+                println!("沿用已打开的音频输出");
+                active_output.clock_updater.reset();
+                active_output
+            }
+            _ => {
+                let sink = match output {
+                    AudioOutput::Device => match Sink::device(&device_selector, &packet_decoder) {
+                        Ok(sink) => sink,
+                        Err(e) => {
+                            // 没有声卡时也要让音频时钟走起来，退回空输出继续播放
+                            println!("音频设备不可用, 改用空输出: {}", e);
+                            error_callback(e);
+                            Sink::clocked(None, &packet_decoder)
+                        }
+                    },
+                    AudioOutput::Null => Sink::clocked(None, &packet_decoder),
+                    AudioOutput::Wav(path) => Sink::clocked(Some(path.clone()), &packet_decoder),
+                };
+                println!(
+                    "音频输出配置 - 采样率: {}, 通道: {}, 格式: {:?}",
+                    sink.sample_rate(),
+                    sink.channels(),
+                    sink.sample_format()
+                );
+                ActiveOutput::open(&sink, &master_clock, &volume_control, &error_callback)?
+            }
+        };
+
+        let converter = match OutputConverter::new(&packet_decoder, &active_output, &downmix) {
+            Ok(converter) => converter,
+            Err(e) => {
+                output_slot.put(active_output);
+                return Err(e);
+            }
+        };
 
+        let finished = Arc::new(AtomicBool::new(false));
+        let thread_finished = finished.clone();
+        let thread_packet_receiver = packet_receiver.clone();
         let receiver_thread = std::thread::Builder::new()
             .name("audio playback thread".into())
             .spawn(move || {
                 smol::block_on(async move {
-                    let output_channel_layout = match config.channels() {
-                        1 => ffmpeg::util::channel_layout::ChannelLayout::MONO,
-                        2 => ffmpeg::util::channel_layout::ChannelLayout::STEREO,
-                        _ => todo!(),
-                    };
-                    println!("音频输出通道布局: {:?}", output_channel_layout);
-
-                    let mut ffmpeg_to_cpal_forwarder = match config.sample_format() {
-                        cpal::SampleFormat::U8 => {
-                            println!("使用U8采样格式");
-                            FFmpegToCPalForwarder::new::<u8>(
-                                config,
-                                &device,
-                                packet_receiver,
-                                packet_decoder,
-                                ffmpeg::util::format::sample::Sample::U8(
-                                    ffmpeg::util::format::sample::Type::Packed,
-                                ),
-                                output_channel_layout,
-                            )
-                        }
-                        cpal::SampleFormat::F32 => {
-                            println!("使用F32采样格式");
-                            FFmpegToCPalForwarder::new::<f32>(
-                                config,
-                                &device,
-                                packet_receiver,
-                                packet_decoder,
-                                ffmpeg::util::format::sample::Sample::F32(
-                                    ffmpeg::util::format::sample::Type::Packed,
-                                ),
-                                output_channel_layout,
-                            )
-                        }
-                        format @ _ => todo!("unsupported cpal output format {:#?}", format),
+                    let mut ffmpeg_to_cpal_forwarder = FFmpegToCPalForwarder {
+                        output: active_output,
+                        converter,
+                        drift_corrector: AudioDriftCorrector::default(),
+                        packet_receiver: thread_packet_receiver,
+                        packet_decoder,
+                        filter_stage: AudioFilterStage::new(filter_settings),
+                        time_base,
+                        master_clock,
+                        volume_control,
+                        downmix,
+                        device_selector,
+                        finished: thread_finished,
+                        error_callback,
+                        event_sender,
                     };
 
                     let packet_receiver_impl = async { ffmpeg_to_cpal_forwarder.stream().await }
@@ -118,26 +314,35 @@ impl AudioPlaybackThread {
                                         println!("音频播放开始");
                                         playing = true;
                                     }
+                                    Ok(command) => {
+                                        println!("音频线程忽略控制命令: {:?}", command);
+                                    }
                                     Err(e) => {
                                         println!("音频控制通道关闭 {}",e);
-                                        return;
+                                        break;
                                     }
                                 }
                             }
                         }
                     }
+
+                    // 输出留给下一个文件继续使用
+                    drop(packet_receiver_impl);
+                    output_slot.put(ffmpeg_to_cpal_forwarder.output);
                 })
             })?;
 
         Ok(Self {
             control_sender,
             packet_sender,
+            packet_receiver,
+            finished,
             receiver_thread: Some(receiver_thread),
         })
     }
 
     pub async fn receive_packet(&self, packet: ffmpeg::codec::packet::packet::Packet) -> bool {
-        match self.packet_sender.send(packet).await {
+        match self.packet_sender.send(PacketMessage::Packet(packet)).await {
             Ok(_) => {
                 // println!("音频包发送成功");
                 true
@@ -149,6 +354,66 @@ impl AudioPlaybackThread {
         }
     }
 
+    // 丢弃队列中尚未解码的数据包
+    pub fn discard_queued_packets(&self) {
+        let mut discarded = 0;
+        while self.packet_receiver.try_recv().is_ok() {
+            discarded += 1;
+        }
+        println!("丢弃音频队列中的数据包: {}", discarded);
+    }
+
+    // 跳转后通知解码线程清空解码器
+    pub async fn flush(&self, target: Option<f64>) {
+        self.discard_queued_packets();
+        // 解码线程处理清空消息前，不能再沿用上一次的结束状态
+        self.finished.store(false, Ordering::Relaxed);
+        if let Err(e) = self.packet_sender.send(PacketMessage::Flush { target }).await {
+            println!("发送音频清空消息失败: {}", e);
+        }
+    }
+
+    // 文件读完后通知解码线程把剩余的采样都解出来
+    pub async fn end_of_stream(&self) {
+        if let Err(e) = self.packet_sender.send(PacketMessage::EndOfStream).await {
+            println!("发送音频结束消息失败: {}", e);
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+
+    // 队列中待解码的数据包占容量的百分比
+    pub fn buffer_percent(&self) -> u8 {
+        queue_fill_percent(&self.packet_sender)
+    }
+
+    // 当前文件读完后换成预先打开的下一个文件的解码器，采样紧接着写入同一个输出
+    pub async fn switch_decoder(&self, decoder: ffmpeg::decoder::Audio, time_base: ffmpeg::Rational) {
+        let message = PacketMessage::Switch { decoder: PreparedDecoder::Audio(decoder), time_base };
+        if let Err(e) = self.packet_sender.send(message).await {
+            println!("发送音频切换消息失败: {}", e);
+        }
+    }
+
+    // 换用另一条音轨的解码器，队列里旧音轨的数据包直接丢弃
+    pub async fn switch_track(&self, decoder: ffmpeg::decoder::Audio, time_base: ffmpeg::Rational, target: Option<f64>) {
+        self.discard_queued_packets();
+        self.finished.store(false, Ordering::Relaxed);
+        let message = PacketMessage::SwitchTrack { decoder: PreparedDecoder::Audio(decoder), time_base, target };
+        if let Err(e) = self.packet_sender.send(message).await {
+            println!("发送音频切换音轨消息失败: {}", e);
+        }
+    }
+
+    // 循环播放跳回起点，采样紧接着写入输出
+    pub async fn restart(&self, target: Option<f64>) {
+        if let Err(e) = self.packet_sender.send(PacketMessage::Restart { target }).await {
+            println!("发送音频循环消息失败: {}", e);
+        }
+    }
+
     pub async fn send_control_message(&self, message: ControlCommand) {
         println!("发送音频控制消息: {:?}", message);
         if let Err(e) = self.control_sender.send(message).await {
@@ -167,21 +432,32 @@ impl Drop for AudioPlaybackThread {
     }
 }
 
+pub(crate) fn open_decoder(stream: &ffmpeg::format::stream::Stream) -> Result<ffmpeg::decoder::Audio, PlayerError> {
+    ffmpeg::codec::Context::from_parameters(stream.parameters())
+        .and_then(|decoder_context| decoder_context.decoder().audio())
+        .map_err(|e| PlayerError::unsupported_codec(stream, e))
+}
+
+// 环形缓冲区满了就等输出消费；输出出错 (failed 置位) 时不再等待，丢弃这一帧
 trait FFMpegToCPalSampleForwarder {
-    fn forward(
-        &mut self,
+    fn forward<'a>(
+        &'a mut self,
         audio_frame: ffmpeg::frame::Audio,
-    ) -> Pin<Box<dyn Future<Output = ()> + '_>>;
+        failed: &'a AtomicBool,
+    ) -> Pin<Box<dyn Future<Output = ()> + 'a>>;
 }
 
+type SamplePipe = Box<dyn FFMpegToCPalSampleForwarder + Send>;
+
 impl<T: Pod, R: RbRef> FFMpegToCPalSampleForwarder for ringbuf::Producer<T, R>
 where
     <R as RbRef>::Rb: RbWrite<T>,
 {
-    fn forward(
-        &mut self,
+    fn forward<'a>(
+        &'a mut self,
         audio_frame: ffmpeg::frame::Audio,
-    ) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+        failed: &'a AtomicBool,
+    ) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
         println!(
             "转发音频帧 - 采样数: {}, 通道数: {}, 格式: {:?}",
             audio_frame.samples(),
@@ -196,97 +472,1222 @@ where
             let cpal_sample_data: &[T] =
                 bytemuck::cast_slice(&audio_frame.data(0)[..expected_bytes]);
 
-            while self.free_len() < cpal_sample_data.len() {
+            // 多声道或大帧一次放不下整个缓冲区，放得下多少先写多少
+            let mut remaining = cpal_sample_data;
+            loop {
+                let pushed = self.push_slice(remaining);
+                remaining = &remaining[pushed..];
+                if remaining.is_empty() || failed.load(Ordering::Relaxed) {
+                    return;
+                }
                 smol::Timer::after(std::time::Duration::from_millis(16)).await;
             }
+        })
+    }
+}
+
+// 设备的采样格式在 FFmpeg 中没有对应的打包格式时，先重采样为相近的格式 S，
+// 写入环形缓冲区前再逐个转换为设备的格式 T
+struct ConvertingProducer<S, T> {
+    producer: HeapProducer<T>,
+    _source: PhantomData<S>,
+}
+
+impl<S, T> ConvertingProducer<S, T> {
+    fn new(producer: HeapProducer<T>) -> Self {
+        Self { producer, _source: PhantomData }
+    }
+}
 
-            // Buffer the samples for playback
-            self.push_slice(cpal_sample_data);
+impl<S: Pod, T: Pod + FromSample<S>> FFMpegToCPalSampleForwarder for ConvertingProducer<S, T> {
+    fn forward<'a>(
+        &'a mut self,
+        audio_frame: ffmpeg::frame::Audio,
+        failed: &'a AtomicBool,
+    ) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+        Box::pin(async move {
+            let expected_bytes =
+                audio_frame.samples() * audio_frame.channels() as usize * core::mem::size_of::<S>();
+            let source_sample_data: &[S] =
+                bytemuck::cast_slice(&audio_frame.data(0)[..expected_bytes]);
+
+            let mut samples = source_sample_data.iter().map(|sample| T::from_sample(*sample)).peekable();
+            loop {
+                self.producer.push_iter(&mut samples);
+                if samples.peek().is_none() || failed.load(Ordering::Relaxed) {
+                    return;
+                }
+                smol::Timer::after(std::time::Duration::from_millis(16)).await;
+            }
         })
     }
 }
 
-struct FFmpegToCPalForwarder {
-    _cpal_stream: cpal::Stream,
-    ffmpeg_to_cpal_pipe: Box<dyn FFMpegToCPalSampleForwarder>,
-    packet_receiver: smol::channel::Receiver<ffmpeg::codec::packet::packet::Packet>,
-    packet_decoder: ffmpeg::decoder::Audio,
-    resampler: ffmpeg::software::resampling::Context,
+// 根据写入环形缓冲区和被 cpal 取走的采样数推算音频时钟
+struct AudioClockUpdater {
+    master_clock: Arc<MasterClock>,
+    samples_per_second: f64,
+    state: Mutex<AudioClockState>,
 }
 
-impl FFmpegToCPalForwarder {
-    fn new<T: Send + Pod + SizedSample + 'static>(
-        config: cpal::SupportedStreamConfig,
-        device: &cpal::Device,
-        packet_receiver: smol::channel::Receiver<ffmpeg::codec::packet::packet::Packet>,
-        packet_decoder: ffmpeg::decoder::Audio,
-        output_format: ffmpeg::util::format::sample::Sample,
-        output_channel_layout: ffmpeg::util::channel_layout::ChannelLayout,
-    ) -> Self {
-        let buffer = HeapRb::new(4096);
-        let (sample_producer, mut sample_consumer) = buffer.split();
-
-        let cpal_stream = device
-            .build_output_stream(
-                &config.config(),
-                move |data, _| {
-                    let filled = sample_consumer.pop_slice(data);
-                    data[filled..].fill(T::EQUILIBRIUM);
-                },
-                move |err| {
-                    eprintln!("error feeding audio stream to cpal: {}", err);
-                },
-                None,
-            )
-            .unwrap();
-
-        cpal_stream.play().unwrap();
-
-        let resampler = ffmpeg::software::resampling::Context::get(
-            packet_decoder.format(),
-            packet_decoder.channel_layout(),
+#[derive(Default)]
+struct AudioClockState {
+    // 已写入的最后一个采样对应的媒体时间
+    written_end_time: Option<f64>,
+    written_samples: u64,
+    played_samples: u64,
+}
+
+impl AudioClockUpdater {
+    fn new(master_clock: Arc<MasterClock>, sample_rate: u32, channels: u16) -> Self {
+        Self {
+            master_clock,
+            samples_per_second: sample_rate as f64 * channels as f64,
+            state: Mutex::new(AudioClockState::default()),
+        }
+    }
+
+    fn samples_written(&self, end_time: Option<f64>, samples: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.written_end_time = match (end_time, state.written_end_time) {
+            (Some(end_time), _) => Some(end_time),
+            (None, Some(previous)) => Some(
+                previous + samples as f64 / self.samples_per_second * self.master_clock.speed(),
+            ),
+            (None, None) => None,
+        };
+        state.written_samples += samples as u64;
+    }
+
+    // 在 cpal 回调中调用，只有真正送进设备的采样才推动音频时钟
+    fn samples_played(&self, samples: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.played_samples += samples as u64;
+        if let Some(written_end_time) = state.written_end_time {
+            let buffered = state.written_samples.saturating_sub(state.played_samples);
+            // 变速时每个输出采样对应的媒体时长按倍速折算
+            let buffered_seconds =
+                buffered as f64 / self.samples_per_second * self.master_clock.speed();
+            self.master_clock.audio().set(written_end_time - buffered_seconds);
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.master_clock.is_paused()
+    }
+
+    // 已写入环形缓冲区但还没被设备取走的采样数
+    fn buffered_samples(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.written_samples.saturating_sub(state.played_samples)
+    }
+
+    // 环形缓冲区里还没播放的采样会继续被取走，重置时保留这部分计数，
+    // 否则之后的缓冲时长会一直少算
+    fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        *state = AudioClockState {
+            written_samples: state.written_samples.saturating_sub(state.played_samples),
+            ..AudioClockState::default()
+        };
+        self.master_clock.audio().reset();
+    }
+}
+
+// 音频最终送到哪里
+enum Sink {
+    Device { device: cpal::Device, config: cpal::SupportedStreamConfig },
+    // 没有声卡时按墙钟节奏消费采样，可以顺便写入 WAV 文件
+    Clocked { wav_path: Option<PathBuf>, sample_rate: u32, channels: u16 },
+}
+
+impl Sink {
+    fn device(
+        device_selector: &AudioDeviceSelector,
+        packet_decoder: &ffmpeg::decoder::Audio,
+    ) -> Result<Self, PlayerError> {
+        let device = find_output_device(device_selector)?;
+        println!("音频输出设备: {:?}", device.name());
+        let config = choose_output_config(
+            &device,
             packet_decoder.rate(),
+            packet_decoder.channels(),
+            |channels| output_channel_layout(channels).is_some(),
+        )?;
+        Ok(Sink::Device { device, config })
+    }
+
+    // 不接声卡时尽量保持源的采样率和声道数，省掉重采样
+    fn clocked(wav_path: Option<PathBuf>, packet_decoder: &ffmpeg::decoder::Audio) -> Self {
+        let channels = match packet_decoder.channels() {
+            channels if output_channel_layout(channels).is_some() => channels,
+            _ => 2,
+        };
+        let sample_rate = match packet_decoder.rate() {
+            0 => 48000,
+            rate => rate,
+        };
+        Sink::Clocked { wav_path, sample_rate, channels }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            Sink::Device { config, .. } => config.sample_rate().0,
+            Sink::Clocked { sample_rate, .. } => *sample_rate,
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        match self {
+            Sink::Device { config, .. } => config.channels(),
+            Sink::Clocked { channels, .. } => *channels,
+        }
+    }
+
+    // 输出设备名称，空输出时为 None
+    fn name(&self) -> Option<String> {
+        match self {
+            Sink::Device { device, .. } => device.name().ok(),
+            Sink::Clocked { .. } => None,
+        }
+    }
+
+    fn sample_format(&self) -> cpal::SampleFormat {
+        match self {
+            Sink::Device { config, .. } => config.sample_format(),
+            Sink::Clocked { .. } => cpal::SampleFormat::F32,
+        }
+    }
+}
+
+// 正在运行的输出，丢弃时停止播放
+enum OutputStream {
+    Device(DeviceStream),
+    Clocked(ClockedSink),
+}
+
+// cpal 的输出流不能跨线程移动，放在单独的线程里持有，
+// 这样输出可以交给下一个文件的音频线程继续使用
+struct DeviceStream {
+    // 丢弃后持有线程停止输出流并退出
+    stop_sender: Option<std::sync::mpsc::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl DeviceStream {
+    fn start<T>(
+        device: cpal::Device,
+        config: cpal::StreamConfig,
+        mut render: impl FnMut(&mut [T]) + Send + 'static,
+        error_callback: ErrorCallback,
+    ) -> Result<Self, PlayerError>
+    where
+        T: SizedSample + Send + 'static,
+    {
+        let (ready_sender, ready_receiver) = std::sync::mpsc::channel();
+        let (stop_sender, stop_receiver) = std::sync::mpsc::channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("audio output thread".into())
+            .spawn(move || {
+                let stream = device
+                    .build_output_stream(
+                        &config,
+                        move |data: &mut [T], _| render(data),
+                        move |err| {
+                            eprintln!("error feeding audio stream to cpal: {}", err);
+                            error_callback(PlayerError::AudioDevice(err.to_string()));
+                        },
+                        None,
+                    )
+                    .map_err(|e| PlayerError::AudioDevice(e.to_string()))
+                    .and_then(|stream| {
+                        stream.play().map_err(|e| PlayerError::AudioDevice(e.to_string()))?;
+                        Ok(stream)
+                    });
+                match stream {
+                    Ok(_stream) => {
+                        let _ = ready_sender.send(Ok(()));
+                        // 发送端被丢弃时 recv 返回，输出流随之停止
+                        let _ = stop_receiver.recv();
+                    }
+                    Err(e) => {
+                        let _ = ready_sender.send(Err(e));
+                    }
+                }
+            })?;
+
+        match ready_receiver.recv() {
+            Ok(Ok(())) => Ok(Self { stop_sender: Some(stop_sender), thread: Some(thread) }),
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                Err(e)
+            }
+            Err(_) => {
+                let _ = thread.join();
+                Err(PlayerError::AudioDevice("音频输出线程意外退出".into()))
+            }
+        }
+    }
+}
+
+impl Drop for DeviceStream {
+    fn drop(&mut self) {
+        self.stop_sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// 变速不变调：重采样之后经过 atempo 滤镜拉伸时长
+struct TempoFilter {
+    graph: ffmpeg::filter::Graph,
+    rate: f64,
+}
+
+impl TempoFilter {
+    fn new(
+        rate: f64,
+        format: ffmpeg::util::format::sample::Sample,
+        channel_layout: ffmpeg::util::channel_layout::ChannelLayout,
+        sample_rate: u32,
+    ) -> Result<Self, ffmpeg::Error> {
+        let mut graph = ffmpeg::filter::Graph::new();
+        let args = format!(
+            "time_base=1/{0}:sample_rate={0}:sample_fmt={1}:channel_layout=0x{2:x}",
+            sample_rate,
+            format.name(),
+            channel_layout.bits()
+        );
+        let abuffer = ffmpeg::filter::find("abuffer").ok_or(ffmpeg::Error::FilterNotFound)?;
+        let abuffersink = ffmpeg::filter::find("abuffersink").ok_or(ffmpeg::Error::FilterNotFound)?;
+        graph.add(&abuffer, "in", &args)?;
+        graph.add(&abuffersink, "out", "")?;
+        if let Some(mut out) = graph.get("out") {
+            out.set_sample_format(format);
+            out.set_channel_layout(channel_layout);
+            out.set_sample_rate(sample_rate);
+        }
+        graph.output("in", 0)?.input("out", 0)?.parse(&atempo_chain(rate))?;
+        graph.validate()?;
+        Ok(Self { graph, rate })
+    }
+
+    fn push(&mut self, frame: &ffmpeg::util::frame::Audio) -> Result<(), ffmpeg::Error> {
+        match self.graph.get("in") {
+            Some(mut input) => input.source().add(frame),
+            None => Err(ffmpeg::Error::FilterNotFound),
+        }
+    }
+
+    fn pull(&mut self, frame: &mut ffmpeg::util::frame::Audio) -> bool {
+        self.graph
+            .get("out")
+            .is_some_and(|mut output| output.sink().frame(frame).is_ok())
+    }
+}
+
+// 解码之后、重采样之前的音频滤镜图，输出和输入保持相同的采样格式、声道布局和采样率，
+// 重采样参数不用跟着变
+struct AudioFilter {
+    graph: ffmpeg::filter::Graph,
+    // 建图时的输入格式，变化后需要重建
+    input: (ffmpeg::util::format::sample::Sample, ffmpeg::util::channel_layout::ChannelLayout, u32),
+    // 输出帧时间戳的时间基秒数
+    output_time_base: f64,
+}
+
+impl AudioFilter {
+    fn new(
+        description: &str,
+        frame: &ffmpeg::util::frame::Audio,
+        time_base: ffmpeg::Rational,
+    ) -> Result<Self, ffmpeg::Error> {
+        let (format, channel_layout, sample_rate) = frame_input(frame);
+        let mut graph = ffmpeg::filter::Graph::new();
+        let args = format!(
+            "time_base={}/{}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+            time_base.numerator(),
+            time_base.denominator(),
+            sample_rate,
+            format.name(),
+            channel_layout.bits()
+        );
+        let abuffer = ffmpeg::filter::find("abuffer").ok_or(ffmpeg::Error::FilterNotFound)?;
+        let abuffersink = ffmpeg::filter::find("abuffersink").ok_or(ffmpeg::Error::FilterNotFound)?;
+        graph.add(&abuffer, "in", &args)?;
+        graph.add(&abuffersink, "out", "")?;
+        if let Some(mut out) = graph.get("out") {
+            out.set_sample_format(format);
+            out.set_channel_layout(channel_layout);
+            out.set_sample_rate(sample_rate);
+        }
+        graph.output("in", 0)?.input("out", 0)?.parse(description)?;
+        graph.validate()?;
+        let output_time_base = match graph.get("out") {
+            Some(output) => f64::from(ffmpeg::Rational::from(unsafe {
+                ffmpeg::ffi::av_buffersink_get_time_base(output.as_ptr())
+            })),
+            None => return Err(ffmpeg::Error::FilterNotFound),
+        };
+        Ok(Self { graph, input: (format, channel_layout, sample_rate), output_time_base })
+    }
+
+    fn push(&mut self, frame: &ffmpeg::util::frame::Audio) -> Result<(), ffmpeg::Error> {
+        match self.graph.get("in") {
+            Some(mut input) => input.source().add(frame),
+            None => Err(ffmpeg::Error::FilterNotFound),
+        }
+    }
+
+    // 通知滤镜已经没有输入，loudnorm 等缓存的采样可以全部取出
+    fn flush(&mut self) -> Result<(), ffmpeg::Error> {
+        match self.graph.get("in") {
+            Some(mut input) => input.source().flush(),
+            None => Err(ffmpeg::Error::FilterNotFound),
+        }
+    }
+
+    // 取出所有已经处理好的帧，附带帧结束时间的秒数
+    fn pull(&mut self, frames: &mut Vec<(ffmpeg::util::frame::Audio, Option<f64>)>) {
+        let Some(mut output) = self.graph.get("out") else {
+            return;
+        };
+        let mut filtered_frame = ffmpeg::util::frame::Audio::empty();
+        while output.sink().frame(&mut filtered_frame).is_ok() {
+            let frame_end = filtered_frame.pts().map(|pts| {
+                pts as f64 * self.output_time_base + filtered_frame.samples() as f64 / filtered_frame.rate() as f64
+            });
+            frames.push((std::mem::replace(&mut filtered_frame, ffmpeg::util::frame::Audio::empty()), frame_end));
+        }
+    }
+}
+
+// 解码出的一帧结束时的秒数，和 ffplay 一样按 best_effort_timestamp 推算
+fn decoded_frame_end(frame: &ffmpeg::util::frame::Audio, time_base: ffmpeg::Rational) -> Option<f64> {
+    frame
+        .timestamp()
+        .map(|pts| pts as f64 * f64::from(time_base) + frame.samples() as f64 / frame.rate() as f64)
+}
+
+// 帧的采样格式、声道布局和采样率，没有声道布局信息时按声道数取默认布局
+fn frame_input(
+    frame: &ffmpeg::util::frame::Audio,
+) -> (ffmpeg::util::format::sample::Sample, ffmpeg::util::channel_layout::ChannelLayout, u32) {
+    let channel_layout = match frame.channel_layout() {
+        layout if layout.is_empty() => ffmpeg::util::channel_layout::ChannelLayout::default(frame.channels() as i32),
+        layout => layout,
+    };
+    (frame.format(), channel_layout, frame.rate())
+}
+
+// 解码和重采样之间的滤镜阶段，设置或输入格式变化时重建滤镜图
+struct AudioFilterStage {
+    settings: Arc<AudioFilterSettings>,
+    // filter 对应的设置版本
+    generation: Option<u64>,
+    filter: Option<AudioFilter>,
+    // 滤镜图已经冲刷或清空，下一帧到来时重建
+    stale: bool,
+}
+
+impl AudioFilterStage {
+    fn new(settings: Arc<AudioFilterSettings>) -> Self {
+        Self { settings, generation: None, filter: None, stale: false }
+    }
+
+    // 跳转或换音轨后丢掉滤镜里缓存的采样
+    fn reset(&mut self) {
+        self.stale = true;
+    }
+
+    // 把一帧送进滤镜，返回可以重采样的帧；没有滤镜时原样返回
+    fn process(
+        &mut self,
+        mut frame: ffmpeg::util::frame::Audio,
+        frame_end: Option<f64>,
+        time_base: ffmpeg::Rational,
+        error_callback: &ErrorCallback,
+    ) -> Vec<(ffmpeg::util::frame::Audio, Option<f64>)> {
+        let generation = self.settings.generation.load(Ordering::Relaxed);
+        let input = frame_input(&frame);
+        if self.generation != Some(generation)
+            || self.stale
+            || self.filter.as_ref().is_some_and(|filter| filter.input != input)
+        {
+            self.generation = Some(generation);
+            self.stale = false;
+            self.filter = None;
+            let description = self.settings.description();
+            if !description.is_empty() {
+                match AudioFilter::new(&description, &frame, time_base) {
+                    Ok(filter) => {
+                        println!("音频滤镜: {} ({:?} {} Hz)", description, input.0, input.2);
+                        self.filter = Some(filter);
+                    }
+                    Err(e) => {
+                        // 设置再次变化之前不经过滤镜直接播放
+                        println!("创建音频滤镜失败: {}", e);
+                        error_callback(PlayerError::Filter { description, source: e });
+                    }
+                }
+            }
+        }
+
+        let mut frames = Vec::new();
+        let Some(filter) = &mut self.filter else {
+            frames.push((frame, frame_end));
+            return frames;
+        };
+        // 和 ffplay 一样用 best_effort_timestamp 作为送进滤镜的时间戳，和不经过滤镜时一致
+        frame.set_pts(frame.timestamp());
+        if let Err(e) = filter.push(&frame) {
+            println!("音频滤镜处理失败: {}", e);
+            error_callback(PlayerError::Filter { description: self.settings.description(), source: e });
+            self.filter = None;
+            return frames;
+        }
+        filter.pull(&mut frames);
+        frames
+    }
+
+    // 文件读完时取出滤镜里剩余的采样，之后的帧用重建的滤镜图处理
+    fn drain(&mut self) -> Vec<(ffmpeg::util::frame::Audio, Option<f64>)> {
+        let mut frames = Vec::new();
+        if let (Some(filter), false) = (&mut self.filter, self.stale) {
+            if filter.flush().is_ok() {
+                filter.pull(&mut frames);
+            }
+        }
+        self.stale = true;
+        frames
+    }
+}
+
+// 单个 atempo 只接受 0.5 到 2.0 (旧版本 FFmpeg)，超出范围时串联多个
+fn atempo_chain(rate: f64) -> String {
+    let mut filters = Vec::new();
+    let mut remaining = rate;
+    while remaining > 2.0 {
+        filters.push("atempo=2.0".to_string());
+        remaining /= 2.0;
+    }
+    while remaining < 0.5 {
+        filters.push("atempo=0.5".to_string());
+        remaining /= 0.5;
+    }
+    filters.push(format!("atempo={}", remaining));
+    filters.join(",")
+}
+
+// 停止或换文件时暂存当前输出，下一个文件的音频线程直接接着用
+#[derive(Default)]
+pub(crate) struct AudioOutputSlot {
+    output: Mutex<Option<ActiveOutput>>,
+}
+
+impl AudioOutputSlot {
+    fn take(&self) -> Option<ActiveOutput> {
+        self.output.lock().unwrap().take()
+    }
+
+    fn put(&self, output: ActiveOutput) {
+        *self.output.lock().unwrap() = Some(output);
+    }
+}
+
+// 当前输出，设备丢失后整体重建
+struct ActiveOutput {
+    _output_stream: OutputStream,
+    ffmpeg_to_cpal_pipe: SamplePipe,
+    output_format: ffmpeg::util::format::sample::Sample,
+    output_channel_layout: ffmpeg::util::channel_layout::ChannelLayout,
+    output_sample_rate: u32,
+    output_channels: usize,
+    clock_updater: Arc<AudioClockUpdater>,
+    // 输出流报错后置位，每个输出单独一个，旧设备迟到的错误不会影响新输出
+    failed: Arc<AtomicBool>,
+}
+
+impl ActiveOutput {
+    fn open(
+        sink: &Sink,
+        master_clock: &Arc<MasterClock>,
+        volume_control: &Arc<VolumeControl>,
+        error_callback: &ErrorCallback,
+    ) -> Result<Self, PlayerError> {
+        let output_channel_layout = output_channel_layout(sink.channels()).ok_or_else(|| {
+            PlayerError::AudioDevice(format!("不支持 {} 声道的输出设备", sink.channels()))
+        })?;
+        println!("音频输出通道布局: {:?}", output_channel_layout);
+
+        let clock_updater = Arc::new(AudioClockUpdater::new(
+            master_clock.clone(),
+            sink.sample_rate(),
+            sink.channels(),
+        ));
+
+        let failed = Arc::new(AtomicBool::new(false));
+        let failure_callback: ErrorCallback = {
+            let failed = failed.clone();
+            let error_callback = error_callback.clone();
+            // 设备消失后 cpal 会反复报错，同一个输出只报告第一次，之后交给 recover_output 重建
+            Arc::new(move |error| {
+                if !failed.swap(true, Ordering::Relaxed) {
+                    error_callback(error);
+                }
+            })
+        };
+
+        use ffmpeg::util::format::sample::{Sample as FFmpegSample, Type::Packed};
+        println!("使用{:?}采样格式", sink.sample_format());
+        // 重采样输出设备对应的打包格式；FFmpeg 不支持的整数格式先输出位宽相同的格式再转换
+        let (output_format, (output_stream, ffmpeg_to_cpal_pipe)): (FFmpegSample, (OutputStream, SamplePipe)) = match sink.sample_format() {
+            cpal::SampleFormat::U8 => (
+                FFmpegSample::U8(Packed),
+                build_output_stream::<u8>(sink, &clock_updater, volume_control, &failure_callback)
+                    .map(|(stream, producer)| (stream, Box::new(producer) as SamplePipe))?,
+            ),
+            cpal::SampleFormat::I8 => (
+                FFmpegSample::U8(Packed),
+                build_output_stream::<i8>(sink, &clock_updater, volume_control, &failure_callback)
+                    .map(|(stream, producer)| (stream, Box::new(ConvertingProducer::<u8, i8>::new(producer)) as SamplePipe))?,
+            ),
+            cpal::SampleFormat::I16 => (
+                FFmpegSample::I16(Packed),
+                build_output_stream::<i16>(sink, &clock_updater, volume_control, &failure_callback)
+                    .map(|(stream, producer)| (stream, Box::new(producer) as SamplePipe))?,
+            ),
+            cpal::SampleFormat::U16 => (
+                FFmpegSample::I16(Packed),
+                build_output_stream::<u16>(sink, &clock_updater, volume_control, &failure_callback)
+                    .map(|(stream, producer)| (stream, Box::new(ConvertingProducer::<i16, u16>::new(producer)) as SamplePipe))?,
+            ),
+            cpal::SampleFormat::I32 => (
+                FFmpegSample::I32(Packed),
+                build_output_stream::<i32>(sink, &clock_updater, volume_control, &failure_callback)
+                    .map(|(stream, producer)| (stream, Box::new(producer) as SamplePipe))?,
+            ),
+            cpal::SampleFormat::U32 => (
+                FFmpegSample::I32(Packed),
+                build_output_stream::<u32>(sink, &clock_updater, volume_control, &failure_callback)
+                    .map(|(stream, producer)| (stream, Box::new(ConvertingProducer::<i32, u32>::new(producer)) as SamplePipe))?,
+            ),
+            cpal::SampleFormat::I64 => (
+                FFmpegSample::I64(Packed),
+                build_output_stream::<i64>(sink, &clock_updater, volume_control, &failure_callback)
+                    .map(|(stream, producer)| (stream, Box::new(producer) as SamplePipe))?,
+            ),
+            cpal::SampleFormat::U64 => (
+                FFmpegSample::I64(Packed),
+                build_output_stream::<u64>(sink, &clock_updater, volume_control, &failure_callback)
+                    .map(|(stream, producer)| (stream, Box::new(ConvertingProducer::<i64, u64>::new(producer)) as SamplePipe))?,
+            ),
+            cpal::SampleFormat::F32 => (
+                FFmpegSample::F32(Packed),
+                build_output_stream::<f32>(sink, &clock_updater, volume_control, &failure_callback)
+                    .map(|(stream, producer)| (stream, Box::new(producer) as SamplePipe))?,
+            ),
+            cpal::SampleFormat::F64 => (
+                FFmpegSample::F64(Packed),
+                build_output_stream::<f64>(sink, &clock_updater, volume_control, &failure_callback)
+                    .map(|(stream, producer)| (stream, Box::new(producer) as SamplePipe))?,
+            ),
+            format => {
+                return Err(PlayerError::AudioDevice(format!("不支持的输出采样格式 {:?}", format)));
+            }
+        };
+
+        Ok(Self {
+            _output_stream: output_stream,
+            ffmpeg_to_cpal_pipe,
             output_format,
             output_channel_layout,
-            config.sample_rate().0,
+            output_sample_rate: sink.sample_rate(),
+            output_channels: sink.channels() as usize,
+            clock_updater,
+            failed,
+        })
+    }
+
+    fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+// 把解码出的采样转换成输出的格式：重采样、下混，倍速不为 1 时再做时间拉伸
+struct OutputConverter {
+    resampler: ffmpeg::software::resampling::Context,
+    // 倍速不为 1 时才有
+    tempo: Option<TempoFilter>,
+}
+
+impl OutputConverter {
+    fn new(
+        packet_decoder: &ffmpeg::decoder::Audio,
+        output: &ActiveOutput,
+        downmix: &DownmixOptions,
+    ) -> Result<Self, PlayerError> {
+        // 部分文件没有声道布局信息，按声道数取默认布局
+        let input_channel_layout = match packet_decoder.channel_layout() {
+            layout if layout.is_empty() => {
+                ffmpeg::util::channel_layout::ChannelLayout::default(packet_decoder.channels() as i32)
+            }
+            layout => layout,
+        };
+        if packet_decoder.channels() as usize > output.output_channels {
+            println!(
+                "音频下混 {} -> {} 声道: {:?}",
+                packet_decoder.channels(),
+                output.output_channels,
+                downmix
+            );
+        }
+
+        let resampler = ffmpeg::software::resampling::Context::get_with(
+            packet_decoder.format(),
+            input_channel_layout,
+            packet_decoder.rate(),
+            output.output_format,
+            output.output_channel_layout,
+            output.output_sample_rate,
+            downmix.resampler_options(),
         )
-        .unwrap();
+        .map_err(|e| PlayerError::Decode { media_type: ffmpeg::media::Type::Audio, source: e })?;
 
-        Self {
-            _cpal_stream: cpal_stream,
-            ffmpeg_to_cpal_pipe: Box::new(sample_producer),
-            packet_receiver,
-            packet_decoder,
-            resampler,
+        Ok(Self { resampler, tempo: None })
+    }
+
+    // 倍速变化时重建时间拉伸滤镜，原速时直接去掉滤镜省掉延迟
+    fn update_tempo(&mut self, rate: f64, output: &ActiveOutput) {
+        let current = self.tempo.as_ref().map_or(1.0, |tempo| tempo.rate);
+        if current == rate {
+            return;
+        }
+        if rate == 1.0 {
+            println!("恢复原速播放");
+            self.tempo = None;
+            return;
+        }
+        match TempoFilter::new(rate, output.output_format, output.output_channel_layout, output.output_sample_rate) {
+            Ok(tempo) => {
+                println!("音频变速: {}x", rate);
+                self.tempo = Some(tempo);
+            }
+            Err(e) => println!("创建音频变速滤镜失败: {}", e),
+        }
+    }
+
+    // 跳转后丢掉滤镜里残留的采样
+    fn reset_tempo(&mut self, output: &ActiveOutput) {
+        if let Some(rate) = self.tempo.take().map(|tempo| tempo.rate) {
+            self.update_tempo(rate, output);
+        }
+    }
+
+    // 让重采样在接下来的 samples 个输入采样里多出或少掉 wanted - samples 个，换算成输出采样数
+    fn compensate(&mut self, samples: usize, wanted: usize, input_rate: u32, output_rate: u32) {
+        let to_output = |count: i64| (count * output_rate as i64 / input_rate.max(1) as i64) as i32;
+        let delta = to_output(wanted as i64 - samples as i64);
+        let distance = to_output(wanted as i64);
+        let result = unsafe { ffmpeg::ffi::swr_set_compensation(self.resampler.as_mut_ptr(), delta, distance) };
+        if result < 0 {
+            println!("设置音频同步修正失败: {}", ffmpeg::Error::from(result));
+        }
+    }
+}
+
+// 不以音频为主时钟时，按音频时钟落后或超前主时钟的秒数增减每帧的采样数，
+// 与 ffplay 的 synchronize_audio 相同。差值取加权平均，避免跟着抖动来回修正
+#[derive(Default)]
+struct AudioDriftCorrector {
+    diff_cumulative: f64,
+    diff_count: u32,
+}
+
+impl AudioDriftCorrector {
+    // 返回这一帧修正后应有的采样数，diff 为音频时钟减去主时钟的秒数
+    fn wanted_samples(&mut self, samples: usize, sample_rate: u32, diff: Option<f64>) -> usize {
+        let Some(diff) = diff.filter(|diff| diff.abs() < AUDIO_NOSYNC_THRESHOLD) else {
+            // 差得太多多半是刚跳转过，等时钟重新对上
+            self.reset();
+            return samples;
+        };
+        let coefficient = (0.01f64.ln() / AUDIO_DIFF_AVG_NB as f64).exp();
+        self.diff_cumulative = diff + coefficient * self.diff_cumulative;
+        if self.diff_count < AUDIO_DIFF_AVG_NB {
+            self.diff_count += 1;
+            return samples;
+        }
+        let average_diff = self.diff_cumulative * (1.0 - coefficient);
+        if average_diff.abs() < AUDIO_DIFF_THRESHOLD {
+            return samples;
+        }
+        let wanted = samples as f64 + diff * sample_rate as f64;
+        let min = samples * (100 - SAMPLE_CORRECTION_PERCENT_MAX) / 100;
+        let max = samples * (100 + SAMPLE_CORRECTION_PERCENT_MAX) / 100;
+        (wanted.max(0.0) as usize).clamp(min, max)
+    }
+
+    fn reset(&mut self) {
+        self.diff_cumulative = 0.0;
+        self.diff_count = 0;
+    }
+}
+
+struct FFmpegToCPalForwarder {
+    output: ActiveOutput,
+    converter: OutputConverter,
+    drift_corrector: AudioDriftCorrector,
+    packet_receiver: smol::channel::Receiver<PacketMessage>,
+    packet_decoder: ffmpeg::decoder::Audio,
+    filter_stage: AudioFilterStage,
+    time_base: ffmpeg::Rational,
+    master_clock: Arc<MasterClock>,
+    volume_control: Arc<VolumeControl>,
+    downmix: DownmixOptions,
+    // 输出出错后优先重新打开的设备
+    device_selector: AudioDeviceSelector,
+    finished: Arc<AtomicBool>,
+    error_callback: ErrorCallback,
+    event_sender: smol::channel::Sender<PlayerEvent>,
+}
+
+impl FFmpegToCPalForwarder {
+    // 新输出的格式可能不同，重采样也要跟着重建
+    fn open_output(&self, sink: &Sink) -> Result<(ActiveOutput, OutputConverter), PlayerError> {
+        let output = ActiveOutput::open(sink, &self.master_clock, &self.volume_control, &self.error_callback)?;
+        let converter = OutputConverter::new(&self.packet_decoder, &output, &self.downmix)?;
+        Ok((output, converter))
+    }
+
+    // 输出设备被拔掉或音频服务重启后重建输出：先试原来选择的设备，再试当前默认设备，
+    // 都不行就改用空输出，保证音频时钟继续走。缓冲区里没播完的几十毫秒采样会丢掉
+    fn recover_output(&mut self) {
+        println!("音频输出出错, 重建输出");
+        let mut selectors = vec![self.device_selector.clone()];
+        if self.device_selector != AudioDeviceSelector::Default {
+            selectors.push(AudioDeviceSelector::Default);
         }
+
+        for selector in selectors {
+            let opened = Sink::device(&selector, &self.packet_decoder)
+                .and_then(|sink| self.open_output(&sink).map(|output| (sink, output)));
+            match opened {
+                Ok((sink, (output, converter))) => {
+                    self.switch_output(&sink, output, converter);
+                    return;
+                }
+                Err(e) => println!("无法打开音频设备 {:?}: {}", selector, e),
+            }
+        }
+
+        let sink = Sink::clocked(None, &self.packet_decoder);
+        match self.open_output(&sink) {
+            Ok((output, converter)) => self.switch_output(&sink, output, converter),
+            Err(e) => println!("无法打开空输出: {}", e),
+        }
+    }
+
+    fn switch_output(&mut self, sink: &Sink, output: ActiveOutput, converter: OutputConverter) {
+        let device = sink.name();
+        println!("音频输出切换到: {:?}", device);
+        self.output = output;
+        self.converter = converter;
+        send_event(&self.event_sender, PlayerEvent::AudioDeviceChanged { device });
     }
 
     async fn stream(&mut self) {
         println!("音频播放线程启动");
+        // 精确跳转时，结束时间早于该时间的帧直接丢弃
+        let mut discard_before: Option<f64> = None;
         loop {
-            let Ok(packet) = self.packet_receiver.recv().await else {
+            let Ok(message) = self.packet_receiver.recv().await else {
                 break;
             };
 
+            if self.output.has_failed() {
+                self.recover_output();
+            }
+
+            // 切换到下一个文件或循环跳回前先把当前解码器里的采样都写进输出，不等播放完
+            let (packet, transition) = match message {
+                PacketMessage::Packet(packet) => (Some(packet), None),
+                PacketMessage::EndOfStream => (None, None),
+                PacketMessage::Switch { decoder: PreparedDecoder::Audio(decoder), time_base } => {
+                    (None, Some(DecoderTransition::Switch { decoder, time_base }))
+                }
+                PacketMessage::Restart { target } => (None, Some(DecoderTransition::Restart { target })),
+                PacketMessage::SwitchTrack { decoder: PreparedDecoder::Audio(decoder), time_base, target } => {
+                    println!("音频换用新的音轨, 精确跳转目标: {:?}", target);
+                    self.packet_decoder = decoder;
+                    self.time_base = time_base;
+                    // 新音轨的声道和采样率可能不同，重新建立到输出格式的转换
+                    match OutputConverter::new(&self.packet_decoder, &self.output, &self.downmix) {
+                        Ok(converter) => self.converter = converter,
+                        Err(e) => (self.error_callback)(e),
+                    }
+                    self.filter_stage.reset();
+                    self.output.clock_updater.reset();
+                    self.drift_corrector.reset();
+                    self.finished.store(false, Ordering::Relaxed);
+                    discard_before = target;
+                    continue;
+                }
+                PacketMessage::Switch { .. } | PacketMessage::SwitchTrack { .. } => {
+                    println!("音频线程收到非音频解码器, 忽略");
+                    continue;
+                }
+                PacketMessage::Flush { target } => {
+                    println!("音频解码器清空, 精确跳转目标: {:?}", target);
+                    self.packet_decoder.flush();
+                    self.filter_stage.reset();
+                    self.output.clock_updater.reset();
+                    self.converter.reset_tempo(&self.output);
+                    self.drift_corrector.reset();
+                    self.finished.store(false, Ordering::Relaxed);
+                    discard_before = target;
+                    continue;
+                }
+            };
+
             // println!("音频包接收到");
-            self.packet_decoder.send_packet(&packet).unwrap();
+            // 没有数据包表示读到了文件末尾，让解码器吐出缓存的采样
+            let sent = match &packet {
+                Some(packet) => self.packet_decoder.send_packet(packet),
+                None => self.packet_decoder.send_eof(),
+            };
+            if let Err(e) = sent {
+                println!("发送音频包到解码器失败: {}", e);
+                (self.error_callback)(PlayerError::Decode {
+                    media_type: ffmpeg::media::Type::Audio,
+                    source: e,
+                });
+                if packet.is_some() {
+                    continue;
+                }
+            }
 
             let mut decoded_frame = ffmpeg::util::frame::Audio::empty();
-            while self
-                .packet_decoder
-                .receive_frame(&mut decoded_frame)
-                .is_ok()
-            {
-                println!("音频解码完成");
-                let mut resampled_frame = ffmpeg::util::frame::Audio::empty();
-                println!("音频重采样");
-                self.resampler
-                    .run(&decoded_frame, &mut resampled_frame)
-                    .unwrap();
-                println!("音频重采样完成");
-                self.ffmpeg_to_cpal_pipe.forward(resampled_frame).await;
-                println!("音频重采样结果发送给CPAL");
+            let mut filter_drained = false;
+            loop {
+                // 经过滤镜后一帧解码输出可能对应零帧或多帧
+                let frames = if self.packet_decoder.receive_frame(&mut decoded_frame).is_ok() {
+                    println!("音频解码完成");
+                    let frame_end = decoded_frame_end(&decoded_frame, self.time_base);
+                    if let (Some(target), Some(frame_end)) = (discard_before, frame_end) {
+                        if frame_end < target {
+                            continue;
+                        }
+                        discard_before = None;
+                    }
+                    let decoded_frame = std::mem::replace(&mut decoded_frame, ffmpeg::util::frame::Audio::empty());
+                    self.filter_stage.process(decoded_frame, frame_end, self.time_base, &self.error_callback)
+                } else if packet.is_none() && !filter_drained {
+                    // 解码器已经吐完，再取出滤镜里缓存的采样
+                    filter_drained = true;
+                    self.filter_stage.drain()
+                } else {
+                    break;
+                };
+
+                for (filtered_frame, frame_end) in frames {
+                    if self.output.has_failed() {
+                        self.recover_output();
+                    }
+
+                    // 以视频或外部时钟为准时，靠增减采样数让音频跟上主时钟
+                    if self.master_clock.sync_mode() != SyncMode::Audio {
+                        let diff = self
+                            .master_clock
+                            .audio()
+                            .get()
+                            .zip(self.master_clock.get())
+                            .map(|(audio_time, master_time)| audio_time - master_time);
+                        let samples = filtered_frame.samples();
+                        let wanted = self.drift_corrector.wanted_samples(samples, filtered_frame.rate(), diff);
+                        if wanted != samples {
+                            let output_rate = self.output.output_sample_rate;
+                            self.converter.compensate(samples, wanted, filtered_frame.rate(), output_rate);
+                        }
+                    }
+
+                    let mut resampled_frame = ffmpeg::util::frame::Audio::empty();
+                    println!("音频重采样");
+                    if let Err(e) = self.converter.resampler.run(&filtered_frame, &mut resampled_frame) {
+                        println!("音频重采样失败: {}", e);
+                        (self.error_callback)(PlayerError::Decode {
+                            media_type: ffmpeg::media::Type::Audio,
+                            source: e,
+                        });
+                        continue;
+                    }
+                    println!("音频重采样完成");
+                    let output = &mut self.output;
+                    self.converter.update_tempo(self.master_clock.speed(), output);
+                    match &mut self.converter.tempo {
+                        None => {
+                            let resampled_samples = resampled_frame.samples() * output.output_channels;
+                            output.ffmpeg_to_cpal_pipe.forward(resampled_frame, &output.failed).await;
+                            output.clock_updater.samples_written(frame_end, resampled_samples);
+                        }
+                        Some(tempo) => {
+                            if let Err(e) = tempo.push(&resampled_frame) {
+                                println!("音频变速失败: {}", e);
+                                continue;
+                            }
+                            // 滤镜内部缓存的几十毫秒不单独计算，输出帧都按输入帧的结束时间记
+                            let mut stretched_frame = ffmpeg::util::frame::Audio::empty();
+                            while tempo.pull(&mut stretched_frame) {
+                                let stretched_samples = stretched_frame.samples() * output.output_channels;
+                                output.ffmpeg_to_cpal_pipe.forward(stretched_frame, &output.failed).await;
+                                output.clock_updater.samples_written(frame_end, stretched_samples);
+                                stretched_frame = ffmpeg::util::frame::Audio::empty();
+                            }
+                        }
+                    }
+                    println!("音频重采样结果发送给CPAL");
+                }
+            }
+
+            match transition {
+                Some(DecoderTransition::Switch { decoder, time_base }) => {
+                    println!("音频切换到下一个文件");
+                    self.packet_decoder = decoder;
+                    self.time_base = time_base;
+                    discard_before = None;
+                    self.drift_corrector.reset();
+                    match OutputConverter::new(&self.packet_decoder, &self.output, &self.downmix) {
+                        Ok(converter) => self.converter = converter,
+                        Err(e) => (self.error_callback)(e),
+                    }
+                }
+                // 环形缓冲区里的采样照常播放，音频时钟随后写入的采样时间跳回起点
+                Some(DecoderTransition::Restart { target }) => {
+                    println!("音频循环跳回, 精确定位目标: {:?}", target);
+                    self.packet_decoder.flush();
+                    self.drift_corrector.reset();
+                    discard_before = target;
+                }
+                None if packet.is_none() => {
+                    // 等环形缓冲区里的采样都送进设备才算播放结束；设备出错时不再等
+                    while self.output.clock_updater.buffered_samples() > 0 && !self.output.has_failed() {
+                        smol::Timer::after(std::time::Duration::from_millis(16)).await;
+                    }
+                    println!("音频播放到结尾");
+                    self.finished.store(true, Ordering::Relaxed);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+// 把环形缓冲区里的采样送给输出，同时推动音频时钟并施加音量
+struct SampleRenderer<T> {
+    sample_consumer: HeapConsumer<T>,
+    clock_updater: Arc<AudioClockUpdater>,
+    volume_control: Arc<VolumeControl>,
+    current_gain: f32,
+    // 每个采样允许的增益变化量
+    gain_step: f32,
+}
+
+impl<T> SampleRenderer<T>
+where
+    T: Pod + SizedSample + FromSample<f32>,
+    f32: FromSample<T>,
+{
+    fn new(
+        sample_consumer: HeapConsumer<T>,
+        clock_updater: Arc<AudioClockUpdater>,
+        volume_control: Arc<VolumeControl>,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Self {
+        Self {
+            sample_consumer,
+            clock_updater,
+            current_gain: volume_control.target_gain(),
+            volume_control,
+            gain_step: 1.0 / (VOLUME_RAMP_SECONDS * sample_rate as f32 * channels as f32),
+        }
+    }
+
+    // 返回从缓冲区取到的采样数，这些采样在 data 的开头，其余部分补静音
+    fn render(&mut self, data: &mut [T]) -> usize {
+        // 暂停时输出静音，缓冲区里的采样留到恢复后继续播放
+        if self.clock_updater.is_paused() {
+            data.fill(T::EQUILIBRIUM);
+            return 0;
+        }
+        let filled = self.sample_consumer.pop_slice(data);
+        data[filled..].fill(T::EQUILIBRIUM);
+
+        let target_gain = self.volume_control.target_gain();
+        for sample in &mut data[..filled] {
+            if self.current_gain != target_gain {
+                self.current_gain = if self.current_gain < target_gain {
+                    (self.current_gain + self.gain_step).min(target_gain)
+                } else {
+                    (self.current_gain - self.gain_step).max(target_gain)
+                };
+            }
+            if self.current_gain != 1.0 {
+                let value = sample.to_sample::<f32>() * self.current_gain;
+                *sample = T::from_sample(value.clamp(-1.0, 1.0));
             }
         }
+        self.clock_updater.samples_played(filled);
+        filled
+    }
+}
+
+// 创建 T 格式的输出流，返回写入对应环形缓冲区的生产者
+fn build_output_stream<T>(
+    sink: &Sink,
+    clock_updater: &Arc<AudioClockUpdater>,
+    volume_control: &Arc<VolumeControl>,
+    error_callback: &ErrorCallback,
+) -> Result<(OutputStream, HeapProducer<T>), PlayerError>
+where
+    T: Send + Pod + SizedSample + FromSample<f32> + 'static,
+    f32: FromSample<T>,
+    i16: FromSample<T>,
+{
+    let capacity = (sink.sample_rate() as f64 * RING_BUFFER_SECONDS) as usize * sink.channels() as usize;
+    let buffer = HeapRb::new(capacity.max(1024));
+    let (sample_producer, sample_consumer) = buffer.split();
+
+    let mut renderer = SampleRenderer::new(
+        sample_consumer,
+        clock_updater.clone(),
+        volume_control.clone(),
+        sink.sample_rate(),
+        sink.channels(),
+    );
+
+    let output_stream = match sink {
+        Sink::Device { device, config } => OutputStream::Device(DeviceStream::start(
+            device.clone(),
+            config.config(),
+            move |data: &mut [T]| {
+                renderer.render(data);
+            },
+            error_callback.clone(),
+        )?),
+        Sink::Clocked { wav_path, sample_rate, channels } => OutputStream::Clocked(ClockedSink::start(
+            *sample_rate,
+            *channels,
+            wav_path.clone(),
+            move |data: &mut [T]| renderer.render(data),
+        )?),
+    };
+
+    Ok((output_stream, sample_producer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 连续若干帧都是同一个差值，返回最后一帧修正后的采样数
+    fn wanted_after(corrector: &mut AudioDriftCorrector, frames: u32, diff: Option<f64>) -> usize {
+        (0..frames).map(|_| corrector.wanted_samples(1024, 48000, diff)).last().unwrap()
+    }
+
+    #[test]
+    fn drift_correction_waits_for_average() {
+        let mut corrector = AudioDriftCorrector::default();
+        assert_eq!(wanted_after(&mut corrector, AUDIO_DIFF_AVG_NB, Some(0.5)), 1024);
+        assert!(corrector.wanted_samples(1024, 48000, Some(0.5)) > 1024);
+    }
+
+    #[test]
+    fn drift_correction_is_limited_per_frame() {
+        let mut corrector = AudioDriftCorrector::default();
+        // 音频超前时多出采样，让音频走慢
+        assert_eq!(wanted_after(&mut corrector, 40, Some(0.5)), 1024 * 110 / 100);
+
+        let mut corrector = AudioDriftCorrector::default();
+        assert_eq!(wanted_after(&mut corrector, 40, Some(-0.5)), 1024 * 90 / 100);
+
+        // 修正量在限制以内时按差值增减
+        let mut corrector = AudioDriftCorrector::default();
+        wanted_after(&mut corrector, 40, Some(0.15));
+        assert_eq!(corrector.wanted_samples(1024, 100, Some(0.15)), 1024 + 15);
+    }
+
+    #[test]
+    fn small_or_unknown_drift_is_not_corrected() {
+        let mut corrector = AudioDriftCorrector::default();
+        assert_eq!(wanted_after(&mut corrector, 40, Some(0.01)), 1024);
+        assert_eq!(wanted_after(&mut corrector, 40, None), 1024);
+        assert_eq!(corrector.diff_count, 0);
+
+        // 差得太多时不修正并重新开始平均
+        let mut corrector = AudioDriftCorrector::default();
+        wanted_after(&mut corrector, 40, Some(0.5));
+        assert_eq!(corrector.wanted_samples(1024, 48000, Some(AUDIO_NOSYNC_THRESHOLD)), 1024);
+        assert_eq!(corrector.diff_count, 0);
+    }
+
+    #[test]
+    fn output_layout_matches_channel_count() {
+        for channels in 1..=8u16 {
+            let layout = output_channel_layout(channels).unwrap();
+            assert_eq!(layout.channels() as u16, channels);
+        }
+        assert!(output_channel_layout(0).is_none());
+        assert!(output_channel_layout(9).is_none());
+    }
+
+    #[test]
+    fn downmix_options_reach_resampler() {
+        let options = DownmixOptions::default().resampler_options();
+        assert_eq!(options.get("lfe_mix_level"), Some("0"));
+        assert_eq!(options.get("matrix_encoding"), Some("none"));
+        let center: f64 = options.get("center_mix_level").unwrap().parse().unwrap();
+        assert!((center - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-12);
+
+        let options = DownmixOptions {
+            center_mix_level: 1.0,
+            surround_mix_level: 0.5,
+            lfe_mix_level: 0.25,
+            matrix_encoding: MatrixEncoding::DolbyProLogic2,
+        }
+        .resampler_options();
+        assert_eq!(options.get("center_mix_level"), Some("1"));
+        assert_eq!(options.get("surround_mix_level"), Some("0.5"));
+        assert_eq!(options.get("lfe_mix_level"), Some("0.25"));
+        assert_eq!(options.get("matrix_encoding"), Some("dplii"));
+    }
+
+    // 只有时间戳和采样数的空帧，足够推算帧的时间
+    fn timed_frame(pts: i64, best_effort: i64, samples: i32, rate: i32) -> ffmpeg::util::frame::Audio {
+        let mut frame = ffmpeg::util::frame::Audio::empty();
+        unsafe {
+            let raw = frame.as_mut_ptr();
+            (*raw).pts = pts;
+            (*raw).best_effort_timestamp = best_effort;
+            (*raw).nb_samples = samples;
+            (*raw).sample_rate = rate;
+        }
+        frame
+    }
+
+    #[test]
+    fn frame_end_uses_best_effort_timestamp() {
+        let time_base = ffmpeg::Rational::new(1, 48000);
+        // 没有 pts 的帧（常见于部分 Ogg/MKV 流）照样有时间
+        let frame = timed_frame(ffmpeg::ffi::AV_NOPTS_VALUE, 96000, 1024, 48000);
+        let end = decoded_frame_end(&frame, time_base).unwrap();
+        assert!((end - (2.0 + 1024.0 / 48000.0)).abs() < 1e-9);
+        // pts 不可靠时以 best_effort_timestamp 为准
+        let frame = timed_frame(0, 48000, 480, 48000);
+        let end = decoded_frame_end(&frame, time_base).unwrap();
+        assert!((end - 1.01).abs() < 1e-9);
+        let frame = timed_frame(ffmpeg::ffi::AV_NOPTS_VALUE, ffmpeg::ffi::AV_NOPTS_VALUE, 1024, 48000);
+        assert_eq!(decoded_frame_end(&frame, time_base), None);
     }
 }
@@ -0,0 +1,225 @@
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use crate::error::PlayerError;
+
+// 选择音频输出设备的方式
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum AudioDeviceSelector {
+    // 默认音频系统的默认输出设备
+    #[default]
+    Default,
+    // 按设备名称选择，不区分大小写，先精确匹配再按包含匹配
+    Name(String),
+    // 按 output_devices() 返回列表中的序号选择
+    Index(usize),
+}
+
+impl AudioDeviceSelector {
+    // 命令行参数既可以是设备序号也可以是设备名称
+    pub fn parse(value: &str) -> Self {
+        match value.parse() {
+            Ok(index) => AudioDeviceSelector::Index(index),
+            Err(_) => AudioDeviceSelector::Name(value.to_string()),
+        }
+    }
+}
+
+// 输出设备支持的一组配置
+#[derive(Clone, Copy, Debug)]
+pub struct AudioOutputConfig {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: cpal::SampleFormat,
+}
+
+// 一个音频输出设备的信息
+#[derive(Clone, Debug)]
+pub struct AudioOutputDevice {
+    pub index: usize,
+    pub host: String,
+    pub name: String,
+    // 是否为所在音频系统的默认输出设备
+    pub is_default: bool,
+    pub configs: Vec<AudioOutputConfig>,
+}
+
+struct DeviceCandidate {
+    host_id: cpal::HostId,
+    device: cpal::Device,
+    name: String,
+    is_default: bool,
+}
+
+// 按音频系统和设备的顺序列出所有输出设备，序号与 AudioDeviceSelector::Index 对应
+fn device_candidates() -> Vec<DeviceCandidate> {
+    let mut candidates = Vec::new();
+    for host_id in cpal::available_hosts() {
+        let host = match cpal::host_from_id(host_id) {
+            Ok(host) => host,
+            Err(e) => {
+                println!("音频系统 {} 不可用: {}", host_id.name(), e);
+                continue;
+            }
+        };
+        let default_name = host.default_output_device().and_then(|device| device.name().ok());
+        let devices = match host.output_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                println!("无法列出 {} 的输出设备: {}", host_id.name(), e);
+                continue;
+            }
+        };
+        for device in devices {
+            let name = device.name().unwrap_or_else(|_| "未知设备".into());
+            candidates.push(DeviceCandidate {
+                host_id,
+                is_default: default_name.as_deref() == Some(name.as_str()),
+                name,
+                device,
+            });
+        }
+    }
+    candidates
+}
+
+// 列出所有音频输出设备及其支持的配置
+pub fn output_devices() -> Vec<AudioOutputDevice> {
+    device_candidates()
+        .into_iter()
+        .enumerate()
+        .map(|(index, candidate)| AudioOutputDevice {
+            index,
+            host: candidate.host_id.name().to_string(),
+            configs: candidate
+                .device
+                .supported_output_configs()
+                .map(|configs| {
+                    configs
+                        .map(|config| AudioOutputConfig {
+                            channels: config.channels(),
+                            min_sample_rate: config.min_sample_rate().0,
+                            max_sample_rate: config.max_sample_rate().0,
+                            sample_format: config.sample_format(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            name: candidate.name,
+            is_default: candidate.is_default,
+        })
+        .collect()
+}
+
+// 找到要使用的输出设备
+pub(crate) fn find_output_device(selector: &AudioDeviceSelector) -> Result<cpal::Device, PlayerError> {
+    match selector {
+        AudioDeviceSelector::Default => cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| PlayerError::AudioDevice("没有可用的音频输出设备".into())),
+        AudioDeviceSelector::Index(index) => device_candidates()
+            .into_iter()
+            .nth(*index)
+            .map(|candidate| candidate.device)
+            .ok_or_else(|| PlayerError::AudioDevice(format!("没有序号为 {} 的音频输出设备", index))),
+        AudioDeviceSelector::Name(name) => {
+            let candidates = device_candidates();
+            let names: Vec<&str> = candidates.iter().map(|candidate| candidate.name.as_str()).collect();
+            position_by_name(&names, name)
+                .and_then(|position| candidates.into_iter().nth(position))
+                .map(|candidate| candidate.device)
+                .ok_or_else(|| PlayerError::AudioDevice(format!("找不到音频输出设备 {:?}", name)))
+        }
+    }
+}
+
+// 按名称查找设备，不区分大小写，先精确匹配再按包含匹配
+fn position_by_name(names: &[&str], name: &str) -> Option<usize> {
+    let wanted = name.to_lowercase();
+    names
+        .iter()
+        .position(|candidate| candidate.to_lowercase() == wanted)
+        .or_else(|| names.iter().position(|candidate| candidate.to_lowercase().contains(&wanted)))
+}
+
+// 输出采样格式的优先顺序，越小越好
+fn sample_format_rank(sample_format: cpal::SampleFormat) -> u8 {
+    match sample_format {
+        cpal::SampleFormat::F32 => 0,
+        cpal::SampleFormat::I16 => 1,
+        cpal::SampleFormat::I32 => 2,
+        _ => 3,
+    }
+}
+
+// 在设备支持的配置中挑选与源最接近的：声道数相同最好，其次宁可多声道也不下混；
+// 采样率能直接支持的优先，避免重采样
+pub(crate) fn choose_output_config(
+    device: &cpal::Device,
+    sample_rate: u32,
+    channels: u16,
+    supported_channels: impl Fn(u16) -> bool,
+) -> Result<cpal::SupportedStreamConfig, PlayerError> {
+    let default_config = device.default_output_config();
+    let configs = match device.supported_output_configs() {
+        Ok(configs) => configs.collect::<Vec<_>>(),
+        Err(e) => {
+            println!("无法获取设备支持的配置, 使用默认配置: {}", e);
+            Vec::new()
+        }
+    };
+
+    let best = configs
+        .into_iter()
+        .filter(|config| supported_channels(config.channels()))
+        .min_by_key(|config| {
+            let channel_rank = match config.channels() {
+                output if output == channels => 0,
+                output if output > channels => u32::from(output - channels),
+                output => 100 + u32::from(channels - output),
+            };
+            let rate_supported =
+                (config.min_sample_rate().0..=config.max_sample_rate().0).contains(&sample_rate);
+            (channel_rank, !rate_supported, sample_format_rank(config.sample_format()))
+        });
+
+    match best {
+        Some(config) => {
+            let rate = sample_rate.clamp(config.min_sample_rate().0, config.max_sample_rate().0);
+            Ok(config.with_sample_rate(cpal::SampleRate(rate)))
+        }
+        None => default_config.map_err(|e| PlayerError::AudioDevice(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selector_is_index_or_name() {
+        assert_eq!(AudioDeviceSelector::parse("0"), AudioDeviceSelector::Index(0));
+        assert_eq!(AudioDeviceSelector::parse("12"), AudioDeviceSelector::Index(12));
+        assert_eq!(AudioDeviceSelector::parse("USB Audio"), AudioDeviceSelector::Name("USB Audio".into()));
+        // 不是非负整数的都当作名称
+        assert_eq!(AudioDeviceSelector::parse("-1"), AudioDeviceSelector::Name("-1".into()));
+        assert_eq!(AudioDeviceSelector::parse("1.5"), AudioDeviceSelector::Name("1.5".into()));
+        assert_eq!(AudioDeviceSelector::parse("hw:1,0"), AudioDeviceSelector::Name("hw:1,0".into()));
+    }
+
+    #[test]
+    fn device_name_prefers_exact_match() {
+        let names = ["HDMI Output", "Speakers (USB Audio)", "USB Audio"];
+        assert_eq!(position_by_name(&names, "usb audio"), Some(2));
+        assert_eq!(position_by_name(&names, "speakers"), Some(1));
+        assert_eq!(position_by_name(&names, "HDMI"), Some(0));
+        assert_eq!(position_by_name(&names, "Bluetooth"), None);
+    }
+
+    #[test]
+    fn float_samples_are_preferred() {
+        assert!(sample_format_rank(cpal::SampleFormat::F32) < sample_format_rank(cpal::SampleFormat::I16));
+        assert!(sample_format_rank(cpal::SampleFormat::I16) < sample_format_rank(cpal::SampleFormat::I32));
+        assert!(sample_format_rank(cpal::SampleFormat::I32) < sample_format_rank(cpal::SampleFormat::U8));
+    }
+}
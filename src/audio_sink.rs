@@ -0,0 +1,256 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use cpal::{FromSample, Sample};
+
+use crate::error::PlayerError;
+
+// 不接声卡时按这个间隔消费一次采样
+const CLOCKED_SINK_INTERVAL: Duration = Duration::from_millis(10);
+
+// 音频输出到哪里
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum AudioOutput {
+    // 输出到音频设备，设备不可用时退回空输出
+    #[default]
+    Device,
+    // 按实时节奏丢弃采样，只用来推动音频时钟
+    Null,
+    // 按实时节奏把采样写入 16 位 PCM 的 WAV 文件。只写入实际播放的采样，
+    // 暂停、缓冲不足和启动时补的静音不写入，文件内容只取决于解码结果
+    Wav(PathBuf),
+}
+
+// 没有音频设备时代替声卡的输出线程：按墙钟节奏向 render 要采样，
+// 这样音频时钟和音视频同步逻辑与接声卡时完全一样。
+// render 返回实际取到的采样数，这些采样在缓冲区开头，其余是补的静音
+pub(crate) struct ClockedSink {
+    stopped: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl ClockedSink {
+    pub(crate) fn start<T>(
+        sample_rate: u32,
+        channels: u16,
+        wav_path: Option<PathBuf>,
+        mut render: impl FnMut(&mut [T]) -> usize + Send + 'static,
+    ) -> Result<Self, PlayerError>
+    where
+        T: Sample + Send + 'static,
+        i16: FromSample<T>,
+    {
+        let mut wav_writer = match wav_path {
+            Some(path) => {
+                println!("音频写入文件: {:?}", path);
+                Some(WavWriter::create(&path, sample_rate, channels).map_err(|e| {
+                    PlayerError::AudioDevice(format!("无法创建 WAV 文件 {:?}: {}", path, e))
+                })?)
+            }
+            None => None,
+        };
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        let thread = std::thread::Builder::new()
+            .name("audio sink thread".into())
+            .spawn(move || {
+                let started_at = Instant::now();
+                let samples_per_second = sample_rate as f64 * channels as f64;
+                let mut rendered: u64 = 0;
+                let mut buffer = Vec::new();
+                while !thread_stopped.load(Ordering::Relaxed) {
+                    std::thread::sleep(CLOCKED_SINK_INTERVAL);
+
+                    // 按总耗时计算应消费的采样数，避免休眠误差累积；只取整帧
+                    let due = (started_at.elapsed().as_secs_f64() * samples_per_second) as u64;
+                    let frames = (due.saturating_sub(rendered) / channels as u64) as usize;
+                    if frames == 0 {
+                        continue;
+                    }
+                    buffer.clear();
+                    buffer.resize(frames * channels as usize, T::EQUILIBRIUM);
+                    let filled = render(&mut buffer).min(buffer.len());
+                    rendered += buffer.len() as u64;
+
+                    if let Some(writer) = &mut wav_writer {
+                        if let Err(e) = writer.write_samples(&buffer[..filled]) {
+                            println!("写入 WAV 文件失败, 停止写入: {}", e);
+                            wav_writer = None;
+                        }
+                    }
+                }
+                if let Some(writer) = wav_writer {
+                    if let Err(e) = writer.finish() {
+                        println!("完成 WAV 文件失败: {}", e);
+                    }
+                }
+            })?;
+
+        Ok(Self { stopped, thread: Some(thread) })
+    }
+}
+
+impl Drop for ClockedSink {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// 16 位 PCM WAV 写入，长度字段在结束时回填
+struct WavWriter {
+    file: BufWriter<File>,
+    data_bytes: u32,
+}
+
+impl WavWriter {
+    const HEADER_BYTES: u32 = 44;
+
+    fn create(path: &Path, sample_rate: u32, channels: u16) -> std::io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = channels * 2;
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { file, data_bytes: 0 })
+    }
+
+    fn write_samples<T: Sample>(&mut self, samples: &[T]) -> std::io::Result<()>
+    where
+        i16: FromSample<T>,
+    {
+        for sample in samples {
+            self.file.write_all(&sample.to_sample::<i16>().to_le_bytes())?;
+        }
+        self.data_bytes = self.data_bytes.saturating_add(samples.len() as u32 * 2);
+        Ok(())
+    }
+
+    fn finish(mut self) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        let riff_bytes = (Self::HEADER_BYTES - 8).saturating_add(self.data_bytes);
+        self.file.write_all(&riff_bytes.to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(Self::HEADER_BYTES as u64 - 4))?;
+        self.file.write_all(&self.data_bytes.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    // 测试用的临时文件，丢弃时删除
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("player-rs-{}-{}.wav", name, std::process::id())))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    #[test]
+    fn wav_header_and_lengths_are_filled_in_on_finish() {
+        let file = TempFile::new("header");
+        let mut writer = WavWriter::create(&file.0, 44100, 2).unwrap();
+        writer.write_samples(&[0.0f32, 1.0, -1.0, 0.5]).unwrap();
+        writer.write_samples(&[0.25f32, -0.25]).unwrap();
+        writer.finish().unwrap();
+
+        let bytes = std::fs::read(&file.0).unwrap();
+        assert_eq!(bytes.len(), 44 + 6 * 2);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4), 36 + 12);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&bytes, 16), 16);
+        assert_eq!(u16_at(&bytes, 20), 1);
+        assert_eq!(u16_at(&bytes, 22), 2);
+        assert_eq!(u32_at(&bytes, 24), 44100);
+        assert_eq!(u32_at(&bytes, 28), 44100 * 4);
+        assert_eq!(u16_at(&bytes, 32), 4);
+        assert_eq!(u16_at(&bytes, 34), 16);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(&bytes, 40), 12);
+        assert_eq!(u16_at(&bytes, 44), 0);
+        assert_eq!(u16_at(&bytes, 50), 0.5f32.to_sample::<i16>() as u16);
+    }
+
+    #[test]
+    fn clocked_sink_consumes_whole_frames_in_real_time() {
+        let consumed = Arc::new(AtomicUsize::new(0));
+        let misaligned = Arc::new(AtomicBool::new(false));
+        let sink = {
+            let consumed = consumed.clone();
+            let misaligned = misaligned.clone();
+            ClockedSink::start(8000, 2, None, move |data: &mut [f32]| {
+                if data.len() % 2 != 0 {
+                    misaligned.store(true, Ordering::Relaxed);
+                }
+                consumed.fetch_add(data.len(), Ordering::Relaxed);
+                0
+            })
+            .unwrap()
+        };
+        std::thread::sleep(Duration::from_millis(300));
+        drop(sink);
+
+        // 8000 Hz 双声道 300 毫秒约 4800 个采样，留出调度误差
+        let consumed = consumed.load(Ordering::Relaxed);
+        assert!((3200..=6400).contains(&consumed), "consumed {} samples", consumed);
+        assert!(!misaligned.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn clocked_sink_writes_only_rendered_samples_to_wav() {
+        let file = TempFile::new("clocked");
+        // 只有 1000 个采样可播放，之后一直是缓冲不足
+        let mut available = 1000usize;
+        let sink = ClockedSink::start(8000, 2, Some(file.0.clone()), move |data: &mut [f32]| {
+            let filled = available.min(data.len());
+            data[..filled].fill(0.5);
+            available -= filled;
+            filled
+        })
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        drop(sink);
+
+        let bytes = std::fs::read(&file.0).unwrap();
+        assert_eq!(u32_at(&bytes, 40), 2000);
+        assert_eq!(bytes.len(), 44 + 2000);
+        let expected = 0.5f32.to_sample::<i16>() as u16;
+        assert!(bytes[44..].chunks(2).all(|sample| u16_at(sample, 0) == expected));
+    }
+}
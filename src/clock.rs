@@ -0,0 +1,249 @@
+use std::sync::Mutex;
+use std::time::Instant;
+
+// 音视频同步时以哪个时钟为准，与 ffplay 的 -sync 参数对应
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    // 以音频播放进度为准，视频据此延迟或丢帧
+    #[default]
+    Audio,
+    // 以视频帧的显示节奏为准
+    Video,
+    // 以独立的墙钟为准
+    External,
+}
+
+// 单个时钟：记录最近一次设置的媒体时间，读取时加上之后按倍速流逝的墙钟时间。
+// 暂停期间时钟停在暂停时刻，恢复后从该位置继续走
+pub struct Clock {
+    state: Mutex<ClockState>,
+}
+
+struct ClockState {
+    time: Option<f64>,
+    updated_at: Instant,
+    paused: bool,
+    speed: f64,
+}
+
+impl ClockState {
+    fn current(&self) -> Option<f64> {
+        if self.paused {
+            self.time
+        } else {
+            self.time.map(|time| time + self.updated_at.elapsed().as_secs_f64() * self.speed)
+        }
+    }
+}
+
+impl Clock {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(ClockState {
+                time: None,
+                updated_at: Instant::now(),
+                paused: false,
+                speed: 1.0,
+            }),
+        }
+    }
+
+    pub fn set(&self, time: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.time = Some(time);
+        state.updated_at = Instant::now();
+    }
+
+    // 时钟尚未开始走时返回 None
+    pub fn get(&self) -> Option<f64> {
+        self.state.lock().unwrap().current()
+    }
+
+    pub fn reset(&self) {
+        self.state.lock().unwrap().time = None;
+    }
+
+    fn set_paused(&self, paused: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.paused == paused {
+            return;
+        }
+        state.time = state.current();
+        state.updated_at = Instant::now();
+        state.paused = paused;
+    }
+
+    fn set_speed(&self, speed: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.time = state.current();
+        state.updated_at = Instant::now();
+        state.speed = speed;
+    }
+}
+
+// 播放器共享的主时钟，音频回调、视频线程和解复用线程都持有它
+pub struct MasterClock {
+    sync_mode: Mutex<SyncMode>,
+    audio: Clock,
+    video: Clock,
+    external: Clock,
+}
+
+impl MasterClock {
+    pub fn new(sync_mode: SyncMode) -> Self {
+        Self {
+            sync_mode: Mutex::new(sync_mode),
+            audio: Clock::new(),
+            video: Clock::new(),
+            external: Clock::new(),
+        }
+    }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        *self.sync_mode.lock().unwrap()
+    }
+
+    // 实际可用的流确定之后再调整同步方式
+    pub fn set_sync_mode(&self, sync_mode: SyncMode) {
+        *self.sync_mode.lock().unwrap() = sync_mode;
+    }
+
+    pub fn audio(&self) -> &Clock {
+        &self.audio
+    }
+
+    pub fn video(&self) -> &Clock {
+        &self.video
+    }
+
+    pub fn external(&self) -> &Clock {
+        &self.external
+    }
+
+    // 外部时钟从第一次出现的媒体时间开始走
+    pub fn start_external(&self, time: f64) {
+        let mut state = self.external.state.lock().unwrap();
+        if state.time.is_none() {
+            state.time = Some(time);
+            state.updated_at = Instant::now();
+        }
+    }
+
+    // 换文件时清空所有时钟，暂停状态和倍速保留
+    pub fn reset(&self) {
+        self.audio.reset();
+        self.video.reset();
+        self.external.reset();
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.audio.set_paused(paused);
+        self.video.set_paused(paused);
+        self.external.set_paused(paused);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.external.state.lock().unwrap().paused
+    }
+
+    // 播放倍速，影响所有时钟的走时速度
+    pub fn set_speed(&self, speed: f64) {
+        self.audio.set_speed(speed);
+        self.video.set_speed(speed);
+        self.external.set_speed(speed);
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.external.state.lock().unwrap().speed
+    }
+
+    // 主时钟当前时间；音频还没开始播放时先用视频时钟顶上
+    pub fn get(&self) -> Option<f64> {
+        match self.sync_mode() {
+            SyncMode::Audio => self.audio.get().or_else(|| self.video.get()),
+            SyncMode::Video => self.video.get(),
+            SyncMode::External => self.external.get(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn assert_near(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("时钟还没开始走");
+        assert!((actual - expected).abs() < 0.05, "{} 与 {} 相差太多", actual, expected);
+    }
+
+    #[test]
+    fn clock_stops_while_paused() {
+        let clock = MasterClock::new(SyncMode::External);
+        assert_eq!(clock.get(), None);
+        clock.external().set(1.0);
+        clock.set_paused(true);
+        assert!(clock.is_paused());
+
+        let paused_at = clock.get();
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(clock.get(), paused_at);
+
+        // 恢复后从暂停的位置接着走，不会把暂停的时间补上
+        clock.set_paused(false);
+        std::thread::sleep(Duration::from_millis(50));
+        let resumed = clock.get().unwrap();
+        assert!(resumed > paused_at.unwrap());
+        assert_near(Some(resumed), 1.05);
+    }
+
+    #[test]
+    fn clock_runs_at_playback_speed() {
+        let clock = MasterClock::new(SyncMode::External);
+        clock.set_speed(2.0);
+        assert_eq!(clock.speed(), 2.0);
+        clock.external().set(10.0);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_near(clock.get(), 10.2);
+
+        // 变速前走过的时间按原来的速度计算
+        clock.set_speed(0.5);
+        let before = clock.get().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_near(clock.get(), before + 0.05);
+    }
+
+    #[test]
+    fn master_time_follows_sync_mode() {
+        let clock = MasterClock::new(SyncMode::Audio);
+        clock.video().set(3.0);
+        // 音频还没出声时先用视频时钟
+        assert_near(clock.get(), 3.0);
+        clock.audio().set(7.0);
+        assert_near(clock.get(), 7.0);
+
+        clock.set_sync_mode(SyncMode::Video);
+        assert_eq!(clock.sync_mode(), SyncMode::Video);
+        assert_near(clock.get(), 3.0);
+
+        clock.set_sync_mode(SyncMode::External);
+        assert_eq!(clock.get(), None);
+        clock.start_external(5.0);
+        clock.start_external(9.0);
+        assert_near(clock.get(), 5.0);
+    }
+
+    #[test]
+    fn reset_keeps_pause_and_speed() {
+        let clock = MasterClock::new(SyncMode::Audio);
+        clock.audio().set(1.0);
+        clock.video().set(1.0);
+        clock.set_speed(1.5);
+        clock.set_paused(true);
+        clock.reset();
+
+        assert_eq!(clock.get(), None);
+        assert!(clock.is_paused());
+        assert_eq!(clock.speed(), 1.5);
+    }
+}
@@ -0,0 +1,89 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// 播放器对外报告的错误。能在 Player::start 中发现的直接返回，
+// 播放线程里发生的通过错误回调异步送出
+#[derive(Clone, Debug)]
+pub enum PlayerError {
+    // 打开输入文件或 URL 失败
+    Open { path: PathBuf, source: ffmpeg::Error },
+    // 没有任何可以解码的音频或视频流
+    NoDecodableStream,
+    // 外挂字幕文件里没有字幕流
+    NoSubtitleStream { path: PathBuf },
+    // 文件里没有这一路流，或者流的类型不对
+    InvalidStream { stream_index: usize, media_type: ffmpeg::media::Type },
+    // 流的编码格式没有可用的解码器
+    UnsupportedCodec { stream_index: usize, codec: ffmpeg::codec::Id, source: ffmpeg::Error },
+    // 音频输出设备不可用或不支持
+    AudioDevice(String),
+    // 播放过程中解码或重采样失败
+    Decode { media_type: ffmpeg::media::Type, source: ffmpeg::Error },
+    // 滤镜描述有误或滤镜图无法处理当前格式
+    Filter { description: String, source: ffmpeg::Error },
+    // 创建播放线程失败
+    Thread(Arc<std::io::Error>),
+}
+
+impl fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayerError::Open { path, source } => write!(f, "无法打开 {:?}: {}", path, source),
+            PlayerError::NoDecodableStream => write!(f, "没有可以解码的音视频流"),
+            PlayerError::NoSubtitleStream { path } => write!(f, "{:?} 里没有字幕", path),
+            PlayerError::InvalidStream { stream_index, media_type } => {
+                write!(f, "流 {} 不是可用的 {:?} 流", stream_index, media_type)
+            }
+            PlayerError::UnsupportedCodec { stream_index, codec, source } => {
+                write!(f, "流 {} 的编码格式 {:?} 不支持: {}", stream_index, codec, source)
+            }
+            PlayerError::AudioDevice(message) => write!(f, "音频设备错误: {}", message),
+            PlayerError::Decode { media_type, source } => {
+                write!(f, "{:?} 解码失败: {}", media_type, source)
+            }
+            PlayerError::Filter { description, source } => write!(f, "滤镜 {:?} 不可用: {}", description, source),
+            PlayerError::Thread(error) => write!(f, "创建播放线程失败: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for PlayerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlayerError::Open { source, .. }
+            | PlayerError::UnsupportedCodec { source, .. }
+            | PlayerError::Decode { source, .. }
+            | PlayerError::Filter { source, .. } => Some(source),
+            PlayerError::Thread(error) => Some(error.as_ref()),
+            PlayerError::NoDecodableStream
+            | PlayerError::NoSubtitleStream { .. }
+            | PlayerError::InvalidStream { .. }
+            | PlayerError::AudioDevice(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PlayerError {
+    fn from(error: std::io::Error) -> Self {
+        PlayerError::Thread(Arc::new(error))
+    }
+}
+
+impl PlayerError {
+    pub(crate) fn unsupported_codec(
+        stream: &ffmpeg::format::stream::Stream,
+        source: ffmpeg::Error,
+    ) -> Self {
+        PlayerError::UnsupportedCodec {
+            stream_index: stream.index(),
+            codec: stream.parameters().id(),
+            source,
+        }
+    }
+}
+
+// 播放线程里用来异步报告错误的回调
+pub type ErrorCallback = Arc<dyn Fn(PlayerError) + Send + Sync>;
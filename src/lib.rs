@@ -1,7 +1,21 @@
 extern crate ffmpeg_next as ffmpeg;
 
+pub mod clock;
+pub mod error;
 pub mod player;
+pub mod playlist;
+pub mod subtitle;
 pub mod video;
 pub mod audio;
+pub mod audio_device;
+pub mod audio_sink;
 
-pub use player::{Player, ControlCommand};
\ No newline at end of file
+pub use clock::SyncMode;
+pub use error::PlayerError;
+pub use player::{Player, PlayerEvent, PlayerState, LoopMode, MIN_RATE, MAX_RATE, PlayerOptions, MediaInfo, StreamInfo, ControlCommand, SeekFlags, SeekPosition};
+pub use playlist::{Playlist, PlaylistItem, RepeatMode};
+pub use subtitle::{SubtitleBitmap, SubtitleContent, SubtitleCue};
+pub use audio::{DownmixOptions, MatrixEncoding};
+pub use audio_device::{output_devices, AudioDeviceSelector, AudioOutputConfig, AudioOutputDevice};
+pub use audio_sink::AudioOutput;
+pub use video::{DisplayGeometry, FrameDropPolicy, FrameStatistics};
\ No newline at end of file
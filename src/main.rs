@@ -1,5 +1,6 @@
 extern crate ffmpeg_next as ffmpeg;
 
+use clap::Parser;
 use ffmpeg::format::Pixel;
 use ffmpeg::frame::Video;
 use sdl2::pixels::PixelFormatEnum;
@@ -8,23 +9,200 @@ use std::error::Error;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
 use std::time::{Duration, Instant};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 mod audio;
+mod audio_device;
+mod audio_sink;
+mod clock;
+mod error;
 mod player;
+mod playlist;
+mod subtitle;
+mod subtitle_overlay;
 mod video;
 
-use crate::player::Player;
+use crate::audio_device::AudioDeviceSelector;
+use crate::audio_sink::AudioOutput;
+use crate::player::{LoopMode, MediaInfo, Player, PlayerEvent, PlayerOptions, SeekFlags, SeekPosition};
+use crate::playlist::{Playlist, PlaylistItem};
+use crate::subtitle_overlay::SubtitleOverlay;
 
 // 默认窗口尺寸
 static SC_WIDTH: AtomicU32 = AtomicU32::new(800);
 static SC_HEIGHT: AtomicU32 = AtomicU32::new(600);
 
+// 命令行参数
+#[derive(Parser, Debug)]
+#[command(version, about = "基于 FFmpeg 和 SDL2 的视频播放器")]
+struct Cli {
+    /// 要播放的文件路径或 URL
+    #[arg(required_unless_present = "list_audio_devices")]
+    input: Option<String>,
+
+    /// 列出所有音频输出设备后退出
+    #[arg(long)]
+    list_audio_devices: bool,
+
+    /// 窗口宽度
+    #[arg(long, default_value_t = 800)]
+    width: u32,
+
+    /// 窗口高度
+    #[arg(long, default_value_t = 600)]
+    height: u32,
+
+    /// 全屏播放
+    #[arg(short, long)]
+    fullscreen: bool,
+
+    /// 开始播放的位置，单位秒
+    #[arg(short = 's', long, value_name = "SECONDS", value_parser = parse_start_position)]
+    start: Option<Duration>,
+
+    /// 初始音量百分比 (0-200)
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u16).range(0..=200))]
+    volume: u16,
+
+    /// 启动时静音
+    #[arg(long)]
+    mute: bool,
+
+    /// 播放次数，0 表示无限循环
+    #[arg(long = "loop", value_name = "COUNT", default_value_t = 1)]
+    loop_count: u32,
+
+    /// 画面缩放方式
+    #[arg(long, value_enum, default_value_t = ScaleMode::Fill)]
+    scale_mode: ScaleMode,
+
+    /// 音频输出设备名称或 --list-audio-devices 列出的序号，默认使用系统默认设备
+    #[arg(long, value_name = "NAME|INDEX")]
+    audio_device: Option<String>,
+
+    /// 不输出声音，按实时节奏丢弃音频采样
+    #[arg(long, conflicts_with = "wav_output")]
+    null_audio: bool,
+
+    /// 把音频写入 WAV 文件而不是播放出来
+    #[arg(long, value_name = "FILE")]
+    wav_output: Option<PathBuf>,
+
+    /// 播放的视频流索引，默认自动选择
+    #[arg(long, value_name = "INDEX")]
+    video_stream: Option<usize>,
+
+    /// 播放的音频流索引，默认自动选择
+    #[arg(long, value_name = "INDEX")]
+    audio_stream: Option<usize>,
+
+    /// 视频滤镜，libavfilter 滤镜描述，如 "crop=1280:720,hflip"
+    #[arg(long = "vf", value_name = "FILTERS", default_value = "")]
+    video_filter: String,
+
+    /// 音频滤镜，libavfilter 滤镜描述，如 "highpass=f=100,loudnorm"
+    #[arg(long = "af", value_name = "FILTERS", default_value = "")]
+    audio_filter: String,
+
+    /// 用 yadif 去隔行
+    #[arg(long)]
+    deinterlace: bool,
+
+    /// 不按视频的旋转信息转正画面
+    #[arg(long)]
+    no_autorotate: bool,
+
+    /// 显示的内嵌字幕流索引，默认自动选择
+    #[arg(long, value_name = "INDEX")]
+    subtitle_stream: Option<usize>,
+
+    /// 不显示内嵌字幕
+    #[arg(long, conflicts_with = "subtitle_stream")]
+    no_subtitles: bool,
+
+    /// 外挂字幕文件 (SRT、ASS、WebVTT)
+    #[arg(long, value_name = "FILE")]
+    sub_file: Option<PathBuf>,
+
+    /// 显示文字字幕的字体文件，默认查找系统字体
+    #[arg(long, value_name = "FILE")]
+    sub_font: Option<PathBuf>,
+
+    /// 字幕延迟秒数，正数表示字幕晚出现
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.0, allow_negative_numbers = true)]
+    sub_delay: f64,
+}
+
+// 解析开始位置，拒绝负数、无穷大和超出 Duration 范围的值
+fn parse_start_position(value: &str) -> Result<Duration, String> {
+    let seconds: f64 = value.parse().map_err(|e| format!("不是有效的秒数: {}", e))?;
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(format!("开始位置必须是非负的有限秒数: {}", value));
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("开始位置超出范围: {}", e))
+}
+
 // 视频播放器配置
 struct PlayerConfig {
     video_path: PathBuf,
     initial_width: u32,
     initial_height: u32,
+    fullscreen: bool,
+    start_time: Option<Duration>,
+    volume: u16,
+    muted: bool,
+    loop_mode: LoopMode,
+    scale_mode: ScaleMode,
+    audio_device: Option<String>,
+    audio_output: AudioOutput,
+    video_stream: Option<usize>,
+    audio_stream: Option<usize>,
+    video_filter: String,
+    audio_filter: String,
+    deinterlace: bool,
+    autorotate: bool,
+    subtitle_stream: Option<usize>,
+    subtitles_enabled: bool,
+    subtitle_file: Option<PathBuf>,
+    subtitle_font: Option<PathBuf>,
+    subtitle_delay: f64,
+}
+
+impl From<Cli> for PlayerConfig {
+    fn from(cli: Cli) -> Self {
+        Self {
+            video_path: cli.input.unwrap_or_default().into(),
+            initial_width: cli.width,
+            initial_height: cli.height,
+            fullscreen: cli.fullscreen,
+            start_time: cli.start.filter(|start| !start.is_zero()),
+            volume: cli.volume,
+            muted: cli.mute,
+            loop_mode: match cli.loop_count {
+                0 => LoopMode::Infinite,
+                1 => LoopMode::Off,
+                count => LoopMode::Count(count),
+            },
+            scale_mode: cli.scale_mode,
+            audio_device: cli.audio_device,
+            audio_output: match (cli.null_audio, cli.wav_output) {
+                (_, Some(path)) => AudioOutput::Wav(path),
+                (true, None) => AudioOutput::Null,
+                (false, None) => AudioOutput::Device,
+            },
+            video_stream: cli.video_stream,
+            audio_stream: cli.audio_stream,
+            video_filter: cli.video_filter,
+            audio_filter: cli.audio_filter,
+            deinterlace: cli.deinterlace,
+            autorotate: !cli.no_autorotate,
+            subtitle_stream: cli.subtitle_stream,
+            subtitles_enabled: !cli.no_subtitles,
+            subtitle_file: cli.sub_file,
+            subtitle_font: cli.sub_font,
+            subtitle_delay: cli.sub_delay,
+        }
+    }
 }
 
 // 窗口状态结构体
@@ -35,11 +213,11 @@ struct WindowState {
 }
 
 impl WindowState {
-    fn new(width: u32, height: u32) -> Self {
+    fn new(width: u32, height: u32, scale_mode: ScaleMode) -> Self {
         Self {
             size: (width, height),
             display_rect: None,
-            scale_mode: ScaleMode::Fill,
+            scale_mode,
         }
     }
 
@@ -84,11 +262,13 @@ impl SdlContext {
     fn new(config: &PlayerConfig) -> Result<Self, Box<dyn Error>> {
         let sdl_context = sdl2::init()?;
         let video_subsystem = sdl_context.video()?;
-        let window = video_subsystem
-            .window("FFmpeg SDL Player", config.initial_width, config.initial_height)
-            .position_centered()
-            .resizable()
-            .build()?;
+        let mut window_builder =
+            video_subsystem.window("FFmpeg SDL Player", config.initial_width, config.initial_height);
+        window_builder.position_centered().resizable();
+        if config.fullscreen {
+            window_builder.fullscreen_desktop();
+        }
+        let window = window_builder.build()?;
 
         let canvas = window.into_canvas().build()?;
         let event_pump = sdl_context.event_pump()?;
@@ -127,7 +307,7 @@ impl FpsCounter {
 }
 
 // 在文件开头添加 ScaleMode 枚举
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
 pub enum ScaleMode {
     Fit,  // 保持原始比例,两侧或者上下留黑
     Fill, // 完全按原比例显示，进行裁剪，画面全屏显示
@@ -135,35 +315,83 @@ pub enum ScaleMode {
 
 fn main() -> Result<(), Box<dyn Error>> {
     // 初始化配置
-    let config = PlayerConfig {
-        video_path: "/Users/chinaxxren/Desktop/a.mp4".into(),
-        initial_width: SC_WIDTH.load(Ordering::Relaxed),
-        initial_height: SC_HEIGHT.load(Ordering::Relaxed),
-    };
+    let cli = Cli::parse();
+
+    if cli.list_audio_devices {
+        print_audio_devices();
+        return Ok(());
+    }
+
+    let config = PlayerConfig::from(cli);
+    SC_WIDTH.store(config.initial_width, Ordering::Relaxed);
+    SC_HEIGHT.store(config.initial_height, Ordering::Relaxed);
 
     println!("初始窗口大小设置为: {}x{}", config.initial_width, config.initial_height);
 
-    println!("开始播放视频: {}", config.video_path.as_os_str().to_str().unwrap());
+    println!("开始播放视频: {}", config.video_path.display());
 
     // 初始化 SDL
     let mut sdl = SdlContext::new(&config)?;
     let (window_width, window_height) = sdl.canvas.output_size()?;
     println!("SDL窗口实际大小: {}x{}", window_width, window_height);
     
-    let mut window_state = WindowState::new(window_width, window_height);
+    let mut window_state = WindowState::new(window_width, window_height, config.scale_mode);
     let mut fps_counter = FpsCounter::new();
     let mut current_texture = None;
+    let ttf_context = sdl2::ttf::init()?;
+    let mut subtitle_overlay =
+        SubtitleOverlay::new(&ttf_context, config.subtitle_font.as_deref(), &sdl.texture_creator);
     let mut last_frame_time = Instant::now();
+    // 已经设置了起点、还没设置终点的 A-B 循环
+    let mut ab_repeat_start = None;
 
     // 创建视频帧通道
     let (frame_sender, frame_receiver) = mpsc::channel::<Video>();
 
+    // 播放列表文件按列表播放，普通文件作为只有一项的列表
+    let mut playlist = if Playlist::is_playlist_file(&config.video_path) {
+        Playlist::from_file(&config.video_path)?
+    } else {
+        let mut playlist = Playlist::new();
+        playlist.append(PlaylistItem::from(config.video_path.clone()));
+        playlist
+    };
+    if playlist.is_empty() {
+        return Err("播放列表为空".into());
+    }
+
     // 初始化播放器
-    let player = Arc::new(Mutex::new(Player::start(
-        config.video_path,
+    let player = Arc::new(Mutex::new(Player::new(
+        PlayerOptions {
+            volume: config.volume as f32 / 100.0,
+            muted: config.muted,
+            audio_device: config
+                .audio_device
+                .as_deref()
+                .map(AudioDeviceSelector::parse)
+                .unwrap_or_default(),
+            audio_output: config.audio_output.clone(),
+            loop_mode: config.loop_mode,
+            video_stream: config.video_stream,
+            audio_stream: config.audio_stream,
+            video_filter: config.video_filter.clone(),
+            audio_filter: config.audio_filter.clone(),
+            deinterlace: config.deinterlace,
+            autorotate: config.autorotate,
+            subtitle_stream: config.subtitle_stream,
+            subtitles_enabled: config.subtitles_enabled,
+            ..PlayerOptions::default()
+        },
         {
+            let mut frame_converter = FrameConverter::new();
             move |frame| {
-                let new_frame = rescaler_for_frame(&frame);
+                let new_frame = match frame_converter.convert(frame) {
+                    Ok(new_frame) => new_frame,
+                    Err(e) => {
+                        eprintln!("转换视频帧失败: {}", e);
+                        return;
+                    }
+                };
                 if let Err(e) = frame_sender.send(new_frame) {
                     eprintln!("发送帧失败: {}", e);
                 }
@@ -172,16 +400,60 @@ fn main() -> Result<(), Box<dyn Error>> {
         move |playing| {
             println!("播放状态: {}", playing);
         },
-    )?));
+        move |error| {
+            eprintln!("播放出错: {}", error);
+        },
+    )));
+    if let Ok(mut player) = player.lock() {
+        // 起点在读第一个数据包之前就生效
+        if let Some(start_time) = config.start_time {
+            println!("从 {:?} 开始播放", start_time);
+        }
+        playlist.play_from(&mut player, 0, config.start_time)?;
+        player.set_subtitle_delay(config.subtitle_delay);
+        // 字幕文件不存在或无法解析时照常播放
+        if let Some(subtitle_file) = &config.subtitle_file {
+            if let Err(e) = player.load_subtitle_file(subtitle_file) {
+                eprintln!("加载字幕失败: {}", e);
+            }
+        }
+    }
+
+    // 纯音频文件没有视频帧
+    if !has_video(&player) {
+        println!("没有视频流, 仅播放音频");
+    }
+    let player_events = player.lock().map(|player| player.events()).map_err(|_| "播放器锁已损坏")?;
 
     // 主循环
     'running: loop {
         // 处理事件
-        if !handle_events(&mut sdl.event_pump, &mut window_state, &player)? {
+        if !handle_events(&mut sdl.event_pump, &mut window_state, &player, &mut playlist, &mut ab_repeat_start)? {
             break 'running;
         }
 
-        // 处理视频帧
+        // 处理播放器事件
+        while let Ok(event) = player_events.try_recv() {
+            match &event {
+                PlayerEvent::Position(_) => {}
+                event => println!("播放器事件: {:?}", event),
+            }
+            // 播放列表据此切换到下一项
+            if let Ok(mut player) = player.lock() {
+                if let Err(e) = playlist.handle_event(&mut player, &event) {
+                    eprintln!("播放列表切换失败: {}", e);
+                }
+            }
+        }
+
+        // 按播放时钟更新字幕，暂停时字幕变化也要重画
+        let subtitles_changed = player
+            .lock()
+            .map(|player| subtitle_overlay.update(player.active_subtitles()))
+            .unwrap_or(false);
+
+        // 处理视频帧，拖入新文件后可能从有视频变成纯音频
+        let has_video = has_video(&player);
         match frame_receiver.try_recv() {
             Ok(frame) => {
                 last_frame_time = Instant::now();
@@ -191,17 +463,25 @@ fn main() -> Result<(), Box<dyn Error>> {
                     &sdl.texture_creator,
                     &mut sdl.canvas,
                     &mut window_state,
+                    &subtitle_overlay,
                 )?;
                 fps_counter.update();
             }
-            Err(mpsc::TryRecvError::Empty) => {
-                check_frame_timeout(last_frame_time, &player)?;
-                std::thread::sleep(Duration::from_millis(1));
-            }
-            Err(mpsc::TryRecvError::Disconnected) => {
+            Err(mpsc::TryRecvError::Disconnected) if has_video => {
                 println!("播放器断开连接，退出循环");
                 break 'running;
             }
+            Err(_) => {
+                if has_video {
+                    if subtitles_changed {
+                        if let Some(texture) = &current_texture {
+                            present_frame(texture, &mut sdl.canvas, &window_state, &subtitle_overlay)?;
+                        }
+                    }
+                    check_frame_timeout(last_frame_time, &player)?;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
         }
     }
 
@@ -212,11 +492,87 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// 打印音频输出设备列表
+fn print_audio_devices() {
+    for device in audio_device::output_devices() {
+        println!(
+            "[{}] {} - {}{}",
+            device.index,
+            device.host,
+            device.name,
+            if device.is_default { " (默认)" } else { "" }
+        );
+        for config in device.configs {
+            println!(
+                "    通道: {}, 采样率: {}-{}, 格式: {:?}",
+                config.channels, config.min_sample_rate, config.max_sample_rate, config.sample_format
+            );
+        }
+    }
+}
+
+// 当前文件是否有视频流，停止状态下视为没有
+fn has_video(player: &Arc<Mutex<Player>>) -> bool {
+    player
+        .lock()
+        .ok()
+        .and_then(|player| player.media_info().map(|media_info| media_info.has_video))
+        .unwrap_or(false)
+}
+
+// 当前音轨之后的下一条音轨，只有一条时返回 None
+fn next_audio_stream(media_info: &MediaInfo) -> Option<usize> {
+    let audio_streams: Vec<usize> = media_info
+        .streams
+        .iter()
+        .filter(|stream| stream.media_type == ffmpeg::media::Type::Audio)
+        .map(|stream| stream.index)
+        .collect();
+    if audio_streams.len() < 2 {
+        println!("没有其他音轨");
+        return None;
+    }
+    let next = audio_streams
+        .iter()
+        .position(|&index| Some(index) == media_info.audio_stream)
+        .map_or(0, |position| (position + 1) % audio_streams.len());
+    Some(audio_streams[next])
+}
+
+// 当前字幕流之后的下一路字幕流，最后一路之后关闭字幕
+fn next_subtitle_stream(media_info: &MediaInfo) -> Option<Option<usize>> {
+    let subtitle_streams: Vec<usize> = media_info
+        .streams
+        .iter()
+        .filter(|stream| stream.media_type == ffmpeg::media::Type::Subtitle)
+        .map(|stream| stream.index)
+        .collect();
+    if subtitle_streams.is_empty() {
+        println!("没有内嵌字幕");
+        return None;
+    }
+    let next = match subtitle_streams.iter().position(|&index| Some(index) == media_info.subtitle_stream) {
+        Some(position) => subtitle_streams.get(position + 1).copied(),
+        None => subtitle_streams.first().copied(),
+    };
+    Some(next)
+}
+
+// 按扩展名判断是否为外挂字幕文件
+fn is_subtitle_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| matches!(extension.to_ascii_lowercase().as_str(), "srt" | "ass" | "ssa" | "vtt"))
+        .unwrap_or(false)
+}
+
 // 处理事件
 fn handle_events(
     event_pump: &mut sdl2::EventPump,
     window_state: &mut WindowState,
     player: &Arc<Mutex<Player>>,
+    playlist: &mut Playlist,
+    ab_repeat_start: &mut Option<Duration>,
 ) -> Result<bool, Box<dyn Error>> {
     for event in event_pump.poll_iter() {
         match event {
@@ -231,6 +587,152 @@ fn handle_events(
                 println!("接收到退出事件");
                 return Ok(false);
             }
+            sdl2::event::Event::DropFile { filename, .. } => {
+                // 拖入播放列表时替换当前列表，拖入普通文件时加到列表末尾并播放
+                println!("加载拖入的文件: {}", filename);
+                let path = PathBuf::from(filename);
+                // 拖入字幕文件时作为当前文件的外挂字幕
+                if is_subtitle_file(&path) {
+                    if let Ok(mut player) = player.lock() {
+                        if let Err(e) = player.load_subtitle_file(&path) {
+                            eprintln!("加载字幕失败: {}", e);
+                        }
+                    }
+                    continue;
+                }
+                let index = if Playlist::is_playlist_file(&path) {
+                    match Playlist::from_file(&path) {
+                        Ok(new_playlist) if !new_playlist.is_empty() => {
+                            *playlist = new_playlist;
+                            0
+                        }
+                        Ok(_) => {
+                            eprintln!("播放列表为空: {:?}", path);
+                            continue;
+                        }
+                        Err(e) => {
+                            eprintln!("读取播放列表失败: {}", e);
+                            continue;
+                        }
+                    }
+                } else {
+                    playlist.append(PlaylistItem::from(path));
+                    playlist.len() - 1
+                };
+                if let Ok(mut player) = player.lock() {
+                    if let Err(e) = playlist.play(&mut player, index) {
+                        eprintln!("加载文件失败: {}", e);
+                    }
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(keycode @ (sdl2::keyboard::Keycode::N | sdl2::keyboard::Keycode::P)),
+                ..
+            } => {
+                // N 和 P 切换到播放列表的下一项或上一项
+                if let Ok(mut player) = player.lock() {
+                    let result = if keycode == sdl2::keyboard::Keycode::N {
+                        playlist.next(&mut player)
+                    } else {
+                        playlist.previous(&mut player)
+                    };
+                    match result {
+                        Ok(true) => {}
+                        Ok(false) => println!("播放列表已到头"),
+                        Err(e) => eprintln!("加载文件失败: {}", e),
+                    }
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(sdl2::keyboard::Keycode::L),
+                ..
+            } => {
+                // L 键依次设置 A-B 循环的起点和终点，再按一次取消
+                if let Ok(mut player) = player.lock() {
+                    if player.ab_repeat().is_some() {
+                        player.clear_ab_repeat();
+                    } else if let Some(position) = player.position() {
+                        match ab_repeat_start.take() {
+                            Some(start) => player.set_ab_repeat(start, position),
+                            None => {
+                                println!("A-B 循环起点: {:?}", position);
+                                *ab_repeat_start = Some(position);
+                            }
+                        }
+                    }
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(sdl2::keyboard::Keycode::A),
+                ..
+            } => {
+                // A 键切换到下一条音轨
+                if let Ok(mut player) = player.lock() {
+                    if let Some(index) = player.media_info().and_then(|media_info| next_audio_stream(&media_info)) {
+                        if let Err(e) = player.select_audio_stream(index) {
+                            eprintln!("切换音轨失败: {}", e);
+                        }
+                    }
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(sdl2::keyboard::Keycode::J),
+                ..
+            } => {
+                // J 键依次切换内嵌字幕流，最后一路之后关闭字幕
+                if let Ok(mut player) = player.lock() {
+                    if let Some(index) = player.media_info().and_then(|media_info| next_subtitle_stream(&media_info)) {
+                        if let Err(e) = player.select_subtitle_stream(index) {
+                            eprintln!("切换字幕失败: {}", e);
+                        }
+                    }
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(keycode @ (sdl2::keyboard::Keycode::Minus | sdl2::keyboard::Keycode::Equals)),
+                ..
+            } => {
+                // - 和 = 每次让字幕提前或推后 0.1 秒
+                let step = if keycode == sdl2::keyboard::Keycode::Equals { 0.1 } else { -0.1 };
+                if let Ok(mut player) = player.lock() {
+                    let delay = player.subtitle_delay() + step;
+                    player.set_subtitle_delay(delay);
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(sdl2::keyboard::Keycode::D),
+                ..
+            } => {
+                // D 键切换去隔行
+                if let Ok(mut player) = player.lock() {
+                    player.toggle_deinterlace();
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(sdl2::keyboard::Keycode::Z),
+                ..
+            } => {
+                // Z 键切换随机播放
+                playlist.set_shuffle(!playlist.is_shuffled());
+                println!("随机播放: {}", playlist.is_shuffled());
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(sdl2::keyboard::Keycode::R),
+                ..
+            } => {
+                // R 键切换重复方式
+                playlist.set_repeat(playlist.repeat().cycle());
+                println!("重复方式: {:?}", playlist.repeat());
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(sdl2::keyboard::Keycode::S),
+                ..
+            } => {
+                // S 键停止播放
+                if let Ok(mut player) = player.lock() {
+                    player.stop();
+                }
+            }
             sdl2::event::Event::KeyDown {
                 keycode: Some(sdl2::keyboard::Keycode::Space),
                 ..
@@ -239,6 +741,69 @@ fn handle_events(
                     player.toggle_pause_playing();
                 }
             }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(keycode @ (sdl2::keyboard::Keycode::Left | sdl2::keyboard::Keycode::Right)),
+                ..
+            } => {
+                // 左右方向键前后跳转10秒
+                let offset = if keycode == sdl2::keyboard::Keycode::Left { -10.0 } else { 10.0 };
+                if let Ok(mut player) = player.lock() {
+                    player.seek(SeekPosition::Relative(offset), SeekFlags::Keyframe);
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(keycode @ (sdl2::keyboard::Keycode::Up | sdl2::keyboard::Keycode::Down)),
+                ..
+            } => {
+                // 上下方向键调节音量，每次 10%
+                let step = if keycode == sdl2::keyboard::Keycode::Up { 0.1 } else { -0.1 };
+                if let Ok(mut player) = player.lock() {
+                    let volume = player.volume() + step;
+                    player.set_volume(volume);
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(keycode @ (sdl2::keyboard::Keycode::LeftBracket | sdl2::keyboard::Keycode::RightBracket)),
+                ..
+            } => {
+                // [ 和 ] 每次减慢或加快 0.25 倍
+                let step = if keycode == sdl2::keyboard::Keycode::RightBracket { 0.25 } else { -0.25 };
+                if let Ok(mut player) = player.lock() {
+                    let rate = player.rate() + step;
+                    player.set_rate(rate);
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(sdl2::keyboard::Keycode::Backspace),
+                ..
+            } => {
+                // 退格键恢复原速
+                if let Ok(mut player) = player.lock() {
+                    player.set_rate(1.0);
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(keycode @ (sdl2::keyboard::Keycode::Comma | sdl2::keyboard::Keycode::Period)),
+                ..
+            } => {
+                // , 和 . 暂停后逐帧后退或前进
+                if let Ok(mut player) = player.lock() {
+                    if keycode == sdl2::keyboard::Keycode::Period {
+                        player.step_forward();
+                    } else {
+                        player.step_backward();
+                    }
+                }
+            }
+            sdl2::event::Event::KeyDown {
+                keycode: Some(sdl2::keyboard::Keycode::Num0),
+                ..
+            } => {
+                // 0 键切换静音
+                if let Ok(mut player) = player.lock() {
+                    player.toggle_mute();
+                }
+            }
             sdl2::event::Event::KeyDown {
                 keycode: Some(sdl2::keyboard::Keycode::M),
                 ..
@@ -256,23 +821,69 @@ fn handle_events(
     Ok(true)
 }
 
-// 缩放视频帧
-fn rescaler_for_frame(frame: &Video) -> Video {
-    // 创建新的视频帧，保持原始尺寸和格式
-    let mut new_frame = Video::empty();
-    let mut context = ffmpeg_next::software::scaling::Context::get(
-        frame.format(),
-        frame.width(),
-        frame.height(),
-        Pixel::YUV420P,
-        frame.width(),  // 使用原始宽度
-        frame.height(), // 使用原始高度
-        ffmpeg::software::scaling::Flags::BILINEAR,
-    )
-    .unwrap();
-
-    context.run(&frame, &mut new_frame).unwrap();
-    new_frame
+// 把解码出的帧转换成 SDL 的 IYUV 纹理能直接上传的 YUV420P。
+// 缩放上下文只在输入格式或大小变化时重建，已经是 YUV420P 的帧不经过 swscale
+struct FrameConverter {
+    scaler: Option<ffmpeg::software::scaling::Context>,
+}
+
+// SAFETY: ffmpeg-next 的缩放上下文只因为持有裸指针才不是 Send，SwsContext 本身不绑定线程。
+// FrameConverter 只放在视频帧回调里，Player 把回调存在 Mutex 里（SharedFrameCallback），
+// 先后加载的文件的视频线程都要先拿到这把锁才能调用，所以任何时候只有一个线程在使用它；
+// 不要把它放到回调以外的地方或者不加锁地共享
+unsafe impl Send for FrameConverter {}
+
+impl FrameConverter {
+    fn new() -> Self {
+        Self { scaler: None }
+    }
+
+    fn convert(&mut self, frame: &Video) -> Result<Video, ffmpeg::Error> {
+        let mut new_frame = Video::empty();
+        if frame.format() == Pixel::YUV420P {
+            // 只增加引用计数，不复制像素
+            unsafe {
+                match ffmpeg::ffi::av_frame_ref(new_frame.as_mut_ptr(), frame.as_ptr()) {
+                    0 => return Ok(new_frame),
+                    e => return Err(ffmpeg::Error::from(e)),
+                }
+            }
+        }
+
+        let input = (frame.format(), frame.width(), frame.height());
+        let scaler = match self.scaler.take() {
+            Some(scaler) if (scaler.input().format, scaler.input().width, scaler.input().height) == input => scaler,
+            _ => {
+                println!("创建缩放上下文: {:?} {}x{} -> YUV420P", input.0, input.1, input.2);
+                // 保持原始尺寸，只转换像素格式
+                ffmpeg::software::scaling::Context::get(
+                    frame.format(),
+                    frame.width(),
+                    frame.height(),
+                    Pixel::YUV420P,
+                    frame.width(),
+                    frame.height(),
+                    ffmpeg::software::scaling::Flags::BILINEAR,
+                )?
+            }
+        };
+        self.scaler.insert(scaler).run(frame, &mut new_frame)?;
+        // 缩放不会带上像素宽高比，显示时要用它算画面比例
+        unsafe {
+            (*new_frame.as_mut_ptr()).sample_aspect_ratio = frame.aspect_ratio().into();
+        }
+        Ok(new_frame)
+    }
+}
+
+// 按像素宽高比换算出的显示大小，非方形像素时拉伸宽度
+fn display_size(frame: &Video) -> (u32, u32) {
+    let sample_aspect_ratio = frame.aspect_ratio();
+    if sample_aspect_ratio.numerator() <= 0 || sample_aspect_ratio.denominator() <= 0 {
+        return (frame.width(), frame.height());
+    }
+    let width = frame.width() as f64 * f64::from(sample_aspect_ratio);
+    ((width.round() as u32).max(1), frame.height())
 }
 
 // 处理视频帧
@@ -282,12 +893,17 @@ fn process_video_frame<'a>(
     texture_creator: &'a sdl2::render::TextureCreator<sdl2::video::WindowContext>,
     canvas: &mut sdl2::render::Canvas<Window>,
     window_state: &mut WindowState,
+    subtitle_overlay: &SubtitleOverlay,
 ) -> Result<(), Box<dyn Error>> {
     let video_width = frame.width();
     let video_height = frame.height();
 
-    // 创建或重新创建纹理（如果尺寸不匹配）
-    if texture.is_none() {
+    // 创建或重新创建纹理（如果尺寸不匹配），裁剪等滤镜会改变画面大小
+    let size_changed = texture.as_ref().is_some_and(|texture| {
+        let query = texture.query();
+        (query.width, query.height) != (video_width, video_height)
+    });
+    if texture.is_none() || size_changed {
         *texture = Some(texture_creator.create_texture_streaming(
             PixelFormatEnum::IYUV,
             video_width,
@@ -307,38 +923,56 @@ fn process_video_frame<'a>(
             frame.stride(2),
         )?;
 
-        // 获取窗口尺寸并更新显示区域
+        // 获取窗口尺寸并更新显示区域，宽高比按显示大小计算
         let (window_width, window_height) = canvas.output_size()?;
+        let (display_width, display_height) = display_size(&frame);
         window_state.update_display_rect(
             window_width,
             window_height,
-            video_width,
-            video_height
+            display_width,
+            display_height
         );
-        
-        let (x, y, w, h) = window_state.display_rect.unwrap();
-        
-        // 只清除一次画布
-        canvas.set_draw_color(sdl2::pixels::Color::BLACK);
-        canvas.clear();
-        
-        // 使用整数坐标以避免子像素渲染
-        let src_rect = sdl2::rect::Rect::new(0, 0, video_width, video_height);
-        let dst_rect = sdl2::rect::Rect::new(x, y, w, h);
-        
-        canvas.copy(tex, Some(src_rect), Some(dst_rect))?;
-        canvas.present();
+
+        present_frame(tex, canvas, window_state, subtitle_overlay)?;
     }
 
     Ok(())
 }
 
+// 画出当前画面并叠加字幕
+fn present_frame(
+    texture: &sdl2::render::Texture,
+    canvas: &mut sdl2::render::Canvas<Window>,
+    window_state: &WindowState,
+    subtitle_overlay: &SubtitleOverlay,
+) -> Result<(), Box<dyn Error>> {
+    let Some((x, y, w, h)) = window_state.display_rect else {
+        return Ok(());
+    };
+
+    // 只清除一次画布
+    canvas.set_draw_color(sdl2::pixels::Color::BLACK);
+    canvas.clear();
+
+    // 使用整数坐标以避免子像素渲染
+    let dst_rect = sdl2::rect::Rect::new(x, y, w, h);
+
+    canvas.copy(texture, None, Some(dst_rect))?;
+    subtitle_overlay.draw(canvas, dst_rect)?;
+    canvas.present();
+    Ok(())
+}
+
 // 检查帧超时
 fn check_frame_timeout(last_frame_time: Instant, player: &Arc<Mutex<Player>>) -> Result<(), Box<dyn Error>> {
     if last_frame_time.elapsed() > Duration::from_secs(5) {
         println!("警告: 5秒未收到新帧");
-        if let Ok(_) = player.lock() {
-            println!("Player 仍然存在且可访问");
+        if let Ok(player) = player.lock() {
+            println!(
+                "Player 仍然存在且可访问, 当前位置: {:?}, 帧统计: {:?}",
+                player.position(),
+                player.frame_statistics()
+            );
         }
     }
     Ok(())
@@ -395,3 +1029,22 @@ fn calculate_display_rect(
     
     (x, y, w, h)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_position_accepts_seconds() {
+        assert_eq!(parse_start_position("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_start_position("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_start_position("1.5"), Ok(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn start_position_rejects_invalid_values() {
+        for value in ["", "abc", "1:30", "-1", "inf", "NaN", "1e30"] {
+            assert!(parse_start_position(value).is_err(), "{}", value);
+        }
+    }
+}
@@ -1,80 +1,1090 @@
 extern crate ffmpeg_next as ffmpeg;
 
-use std::path::PathBuf;
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use futures::{future::OptionFuture, FutureExt};
+use futures::{FutureExt, StreamExt};
 
+use super::audio_device::AudioDeviceSelector;
+use super::audio_sink::AudioOutput;
+use super::clock::{MasterClock, SyncMode};
+use super::error::{ErrorCallback, PlayerError};
+use super::subtitle::{self, SubtitleCue, SubtitleDecoder, SubtitleTrack};
 use super::{audio, video};
 
+// 支持的播放倍速范围
+pub const MIN_RATE: f64 = 0.5;
+pub const MAX_RATE: f64 = 4.0;
+
+// 解复用线程检查播放位置和缓冲状态的间隔
+const EVENT_TICK_INTERVAL: Duration = Duration::from_millis(250);
+// 所有数据包队列都低于该百分比时认为在缓冲
+const BUFFERING_THRESHOLD_PERCENT: u8 = 5;
 
 #[derive(Clone, Copy, Debug)]
 pub enum ControlCommand {
     Play,
     Pause,
+    Seek { position: SeekPosition, flags: SeekFlags },
+    // 暂停状态下显示下一帧
+    StepForward,
+    // 暂停状态下退回上一帧：跳到之前的关键帧再解码到上一帧的时间
+    StepBackward,
+    // 播放中换用另一条音轨，参数为流索引
+    SelectAudioStream(usize),
+    // 换用另一路内嵌字幕流，None 表示关闭
+    SelectSubtitleStream(Option<usize>),
 }
 
-pub struct Player {
+// 跳转目标
+#[derive(Clone, Copy, Debug)]
+pub enum SeekPosition {
+    // 从文件开头算起的绝对位置
+    Absolute(Duration),
+    // 相对当前位置的偏移秒数，负数表示后退
+    Relative(f64),
+}
+
+// 跳转方式
+#[derive(Clone, Copy, Debug)]
+pub enum SeekFlags {
+    // 跳到目标之前最近的关键帧，速度快但位置不精确
+    Keyframe,
+    // 先跳到关键帧，再由解码线程丢弃目标之前的帧
+    Accurate,
+}
+
+// 播放线程数据包队列中传递的消息
+pub enum PacketMessage {
+    Packet(ffmpeg::codec::packet::packet::Packet),
+    // 跳转后清空解码器，target 为需要精确定位的目标秒数
+    Flush { target: Option<f64> },
+    // 文件已经读完
+    EndOfStream,
+    // 无缝切换到下一个文件：先解完当前解码器里剩余的数据，再换用新的解码器
+    Switch { decoder: PreparedDecoder, time_base: ffmpeg::Rational },
+    // 循环播放跳回起点：先解完剩余的数据，再复位解码器接着用，target 为需要精确定位的起点
+    Restart { target: Option<f64> },
+    // 换了音轨：丢掉旧解码器里的数据直接换用新的解码器，之后按 Flush 处理
+    SwitchTrack { decoder: PreparedDecoder, time_base: ffmpeg::Rational, target: Option<f64> },
+}
+
+// 播放线程解完当前解码器里剩余的数据后要做的事
+pub(crate) enum DecoderTransition<D> {
+    // 换用下一个文件的解码器
+    Switch { decoder: D, time_base: ffmpeg::Rational },
+    // 复位解码器，从循环起点继续
+    Restart { target: Option<f64> },
+}
+
+// 预先打开的下一个文件的解码器
+pub enum PreparedDecoder {
+    Video { decoder: ffmpeg::decoder::Video, geometry: video::DisplayGeometry },
+    Audio(ffmpeg::decoder::Audio),
+}
+
+// 数据包队列已用容量的百分比
+pub(crate) fn queue_fill_percent(sender: &smol::channel::Sender<PacketMessage>) -> u8 {
+    match sender.capacity() {
+        Some(capacity) if capacity > 0 => (sender.len() * 100 / capacity).min(100) as u8,
+        _ => 100,
+    }
+}
+
+// 播放器通过事件通道对外发出的通知
+#[derive(Clone, Debug)]
+pub enum PlayerEvent {
+    // 文件打开完成，播放线程已经就绪
+    Opened { duration: Option<Duration>, streams: MediaInfo },
+    // 播放中定期报告当前位置
+    Position(Duration),
+    // 所有流都播放完毕
+    EndOfStream,
+    // 数据包队列见底时报告填充百分比，恢复后报告 100
+    Buffering(u8),
+    // 跳转完成，附带跳转目标位置
+    Seeked(Duration),
+    // 音频输出出错后切换到了新的设备，device 为 None 表示改用空输出
+    AudioDeviceChanged { device: Option<String> },
+    // 播放过程中出现的错误，同时也会交给错误回调
+    Error(PlayerError),
+    // 播放器状态变化
+    StateChanged(PlayerState),
+    // 当前文件读完, 无缝切换到了预加载的文件，随后会发出新文件的 Opened
+    PreloadedStarted { path: PathBuf },
+    // 循环播放跳回了起点，附带起点位置
+    Looped(Duration),
+    // 换用了另一条音轨，附带流索引
+    AudioStreamChanged(usize),
+}
+
+// 播放器状态
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlayerState {
+    // 没有加载文件
+    Stopped,
+    // 正在打开文件并启动播放线程
+    Loading,
+    Playing,
+    Paused,
+    // 所有流都播放完毕，跳转后回到播放状态
+    Ended,
+}
+
+// 切换播放器状态，状态确实变化时发出事件；expected 不为 None 时只在当前状态与之相同时切换
+fn change_state(
+    state: &Mutex<PlayerState>,
+    expected: Option<PlayerState>,
+    new_state: PlayerState,
+    event_sender: &smol::channel::Sender<PlayerEvent>,
+) {
+    let mut state = state.lock().unwrap();
+    if *state == new_state || expected.is_some_and(|expected| *state != expected) {
+        return;
+    }
+    println!("播放器状态: {:?} -> {:?}", *state, new_state);
+    *state = new_state;
+    send_event(event_sender, PlayerEvent::StateChanged(new_state));
+}
+
+// 事件通道满了说明没人读取，直接丢弃新事件
+pub(crate) fn send_event(event_sender: &smol::channel::Sender<PlayerEvent>, event: PlayerEvent) {
+    if let Err(e) = event_sender.try_send(event) {
+        println!("丢弃播放器事件: {:?}", e.into_inner());
+    }
+}
+
+// 整个文件的循环方式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoopMode {
+    // 播放一遍后结束
+    #[default]
+    Off,
+    // 总共播放指定的遍数
+    Count(u32),
+    // 无限循环
+    Infinite,
+}
+
+// 循环播放设置，Player 写入，解复用线程读取
+#[derive(Default)]
+struct LoopSettings {
+    mode: LoopMode,
+    // 按遍数循环时已经播完的遍数
+    completed: u32,
+    // A-B 循环的起点和终点秒数
+    ab_repeat: Option<(f64, f64)>,
+}
+
+impl LoopSettings {
+    // A-B 循环的时间点只对当前文件有意义，换文件时一并清掉
+    fn reset_for_new_file(&mut self) {
+        self.completed = 0;
+        self.ab_repeat = None;
+    }
+
+    // 读到文件末尾时决定是否跳回起点，返回起点秒数和是否需要精确定位；
+    // file_start 为文件第一个时间戳的秒数
+    fn next_loop_start(&mut self, file_start: f64) -> Option<(f64, bool)> {
+        // 终点在文件末尾之后的 A-B 循环在这里跳回
+        if let Some((start, _)) = self.ab_repeat {
+            return Some((start, true));
+        }
+        match self.mode {
+            LoopMode::Off => None,
+            LoopMode::Infinite => Some((file_start, false)),
+            LoopMode::Count(count) => {
+                self.completed += 1;
+                if self.completed < count {
+                    println!("循环播放第 {}/{} 遍", self.completed + 1, count);
+                    Some((file_start, false))
+                } else {
+                    // 播完后再从头播放时重新计数
+                    self.completed = 0;
+                    None
+                }
+            }
+        }
+    }
+
+    // 解复用读到终点时会直接跳回起点，已经排队的数据包照常播完。设置终点时解复用已经读过了终点的话，
+    // 播放位置到达终点时还没有跳回，这时返回起点，由调用方清空队列后跳转
+    fn ab_restart_due(&self, position: Option<f64>, read_position: f64) -> Option<f64> {
+        let (start, end) = self.ab_repeat?;
+        (position? >= end && read_position >= end).then_some(start)
+    }
+}
+
+// 启动播放器时的可选配置
+#[derive(Clone, Debug)]
+pub struct PlayerOptions {
+    pub sync_mode: SyncMode,
+    pub frame_drop: video::FrameDropPolicy,
+    // 解码后、交给视频回调前经过的 libavfilter 滤镜，如 "crop=1280:720,hflip"，空字符串表示不用滤镜
+    pub video_filter: String,
+    // 在其它视频滤镜之前用 yadif 去隔行
+    pub deinterlace: bool,
+    // 按手机视频等的显示矩阵把画面转正
+    pub autorotate: bool,
+    // 初始音量，1.0 为原始音量
+    pub volume: f32,
+    pub muted: bool,
+    // 解码后、重采样前经过的 libavfilter 音频滤镜，如 "highpass=f=100,loudnorm"，空字符串表示不用滤镜
+    pub audio_filter: String,
+    // 源声道多于输出设备时的下混参数
+    pub downmix: audio::DownmixOptions,
+    pub audio_device: AudioDeviceSelector,
+    // 输出到声卡、空输出或 WAV 文件
+    pub audio_output: AudioOutput,
+    pub loop_mode: LoopMode,
+    // 指定播放的视频流、音频流和字幕流索引，None 或索引无效时自动选择
+    pub video_stream: Option<usize>,
+    pub audio_stream: Option<usize>,
+    pub subtitle_stream: Option<usize>,
+    // 为 false 时不自动打开内嵌字幕
+    pub subtitles_enabled: bool,
+}
+
+impl Default for PlayerOptions {
+    fn default() -> Self {
+        Self {
+            sync_mode: SyncMode::default(),
+            frame_drop: video::FrameDropPolicy::default(),
+            video_filter: String::new(),
+            deinterlace: false,
+            autorotate: true,
+            volume: 1.0,
+            muted: false,
+            audio_filter: String::new(),
+            downmix: audio::DownmixOptions::default(),
+            audio_device: AudioDeviceSelector::default(),
+            audio_output: AudioOutput::default(),
+            loop_mode: LoopMode::default(),
+            video_stream: None,
+            audio_stream: None,
+            subtitle_stream: None,
+            subtitles_enabled: true,
+        }
+    }
+}
+
+// 打开文件后得到的媒体信息，has_video 和 has_audio 只统计能够正常解码播放的流
+#[derive(Clone, Debug)]
+pub struct MediaInfo {
+    pub has_video: bool,
+    pub has_audio: bool,
+    pub duration: Option<Duration>,
+    // 文件里的所有流，包括没有选中播放的
+    pub streams: Vec<StreamInfo>,
+    // 正在播放的视频流、音频流和内嵌字幕流索引
+    pub video_stream: Option<usize>,
+    pub audio_stream: Option<usize>,
+    pub subtitle_stream: Option<usize>,
+}
+
+impl MediaInfo {
+    fn new(
+        input_context: &ffmpeg::format::context::Input,
+        video_stream: Option<usize>,
+        audio_stream: Option<usize>,
+    ) -> Self {
+        let duration = input_context.duration();
+        Self {
+            has_video: video_stream.is_some(),
+            has_audio: audio_stream.is_some(),
+            duration: (duration > 0)
+                .then(|| Duration::from_secs_f64(duration as f64 / ffmpeg::ffi::AV_TIME_BASE as f64)),
+            streams: input_context.streams().map(|stream| StreamInfo::new(&stream)).collect(),
+            video_stream,
+            audio_stream,
+            subtitle_stream: None,
+        }
+    }
+}
+
+// 文件里的一路流，用来列出可选的音轨和视频
+#[derive(Clone, Debug)]
+pub struct StreamInfo {
+    pub index: usize,
+    pub media_type: ffmpeg::media::Type,
+    pub codec: ffmpeg::codec::Id,
+    // 元数据里的语言和标题
+    pub language: Option<String>,
+    pub title: Option<String>,
+    // 音频流的声道数
+    pub channels: Option<u16>,
+    // 视频流的编码宽高，没有计入旋转和像素宽高比
+    pub resolution: Option<(u32, u32)>,
+    // 视频流的旋转方向和容器里记录的像素宽高比
+    pub geometry: Option<video::DisplayGeometry>,
+    pub disposition: ffmpeg::format::stream::Disposition,
+}
+
+// FFmpeg 5.1 起声道数在 ch_layout 里，之前的 channels 字段在 7.0 删掉了
+#[cfg(ffmpeg_5_1)]
+fn parameter_channels(parameters: &ffmpeg::ffi::AVCodecParameters) -> i32 {
+    parameters.ch_layout.nb_channels
+}
+
+#[cfg(not(ffmpeg_5_1))]
+fn parameter_channels(parameters: &ffmpeg::ffi::AVCodecParameters) -> i32 {
+    parameters.channels
+}
+
+impl StreamInfo {
+    fn new(stream: &ffmpeg::format::stream::Stream) -> Self {
+        let parameters = stream.parameters();
+        let media_type = parameters.medium();
+        // ffmpeg-next 没有包装这几个编码参数，直接读 AVCodecParameters
+        let (width, height, channels) = unsafe {
+            let parameters = &*parameters.as_ptr();
+            (parameters.width, parameters.height, parameter_channels(parameters))
+        };
+        let metadata = stream.metadata();
+        Self {
+            index: stream.index(),
+            media_type,
+            codec: parameters.id(),
+            language: metadata.get("language").map(str::to_string),
+            title: metadata.get("title").map(str::to_string),
+            channels: (media_type == ffmpeg::media::Type::Audio && channels > 0).then_some(channels as u16),
+            resolution: (media_type == ffmpeg::media::Type::Video && width > 0 && height > 0)
+                .then_some((width as u32, height as u32)),
+            geometry: (media_type == ffmpeg::media::Type::Video).then(|| video::DisplayGeometry::new(stream)),
+            disposition: stream.disposition(),
+        }
+    }
+}
+
+// 按指定的索引选择流，没有指定、索引无效或类型不符时自动选择
+fn select_stream(
+    input_context: &ffmpeg::format::context::Input,
+    media_type: ffmpeg::media::Type,
+    requested: Option<usize>,
+) -> Option<ffmpeg::format::stream::Stream<'_>> {
+    if let Some(index) = requested {
+        match input_context.stream(index) {
+            Some(stream) if stream.parameters().medium() == media_type => return Some(stream),
+            _ => println!("流 {} 不是可用的 {:?} 流, 改为自动选择", index, media_type),
+        }
+    }
+    input_context.streams().best(media_type)
+}
+
+// 选择并打开字幕流，打不开时不显示字幕
+fn open_subtitle_stream(
+    input_context: &ffmpeg::format::context::Input,
+    requested: Option<usize>,
+    video_stream: Option<usize>,
+) -> Option<(usize, SubtitleDecoder)> {
+    let stream = select_stream(input_context, ffmpeg::media::Type::Subtitle, requested)?;
+    // 图形字幕没有写画面大小时按视频大小摆放
+    let video_size = video_stream
+        .and_then(|index| input_context.stream(index))
+        .and_then(|video_stream| StreamInfo::new(&video_stream).resolution);
+    match SubtitleDecoder::open(&stream, video_size) {
+        Ok(decoder) => {
+            println!("字幕流索引: {}", stream.index());
+            Some((stream.index(), decoder))
+        }
+        Err(e) => {
+            println!("字幕流不可用: {}", e);
+            None
+        }
+    }
+}
+
+// 转发任务在两个数据包之间处理的请求
+enum ForwarderRequest {
+    Seek(SeekRequest),
+    SelectAudioStream(usize),
+    SelectSubtitleStream(Option<usize>),
+}
+
+// 转发任务处理的跳转目标
+enum SeekTarget {
+    // 调用方请求的位置，绝对位置从文件开头算起
+    Requested(SeekPosition),
+    // 主时钟上的时间，即流时间戳换算的秒数，播放器内部跳转用
+    MediaTime(f64),
+}
+
+// 转发任务处理的跳转请求
+struct SeekRequest {
+    target: SeekTarget,
+    flags: SeekFlags,
+    // 跳转完成后让视频线程在暂停状态下显示一帧
+    step: bool,
+}
+
+// 解复用线程转发数据包的目标流，无缝切换后索引和时间基换成下一个文件的
+struct StreamTarget<T> {
+    index: Cell<usize>,
+    time_base: Cell<f64>,
+    playback_thread: T,
+}
+
+// 为无缝切换预先打开的下一个文件
+struct PreparedMedia {
+    path: PathBuf,
+    input_context: ffmpeg::format::context::Input,
+    video: Option<PreparedStream<ffmpeg::decoder::Video>>,
+    audio: Option<PreparedStream<ffmpeg::decoder::Audio>>,
+    subtitle: Option<(usize, SubtitleDecoder)>,
+    // 视频的帧间隔秒数
+    frame_duration: f64,
+    // 视频的旋转方向和像素宽高比
+    video_geometry: video::DisplayGeometry,
+    media_info: MediaInfo,
+}
+
+struct PreparedStream<D> {
+    index: usize,
+    time_base: ffmpeg::Rational,
+    decoder: D,
+}
+
+impl PreparedMedia {
+    fn open(path: PathBuf, options: &PlayerOptions) -> Result<Self, PlayerError> {
+        let input_context = ffmpeg::format::input(&path)
+            .map_err(|source| PlayerError::Open { path: path.clone(), source })?;
+
+        let mut frame_duration = 0.0;
+        let mut video_geometry = video::DisplayGeometry::default();
+        let video = select_stream(&input_context, ffmpeg::media::Type::Video, options.video_stream).and_then(|stream| {
+            match video::open_decoder(&stream) {
+                Ok(decoder) => {
+                    frame_duration = video::frame_duration(&stream);
+                    video_geometry = video::DisplayGeometry::new(&stream);
+                    Some(PreparedStream { index: stream.index(), time_base: stream.time_base(), decoder })
+                }
+                Err(e) => {
+                    println!("预加载的视频流不可用: {}", e);
+                    None
+                }
+            }
+        });
+        let audio = select_stream(&input_context, ffmpeg::media::Type::Audio, options.audio_stream).and_then(|stream| {
+            match audio::open_decoder(&stream) {
+                Ok(decoder) => {
+                    Some(PreparedStream { index: stream.index(), time_base: stream.time_base(), decoder })
+                }
+                Err(e) => {
+                    println!("预加载的音频流不可用: {}", e);
+                    None
+                }
+            }
+        });
+        if video.is_none() && audio.is_none() {
+            return Err(PlayerError::NoDecodableStream);
+        }
+
+        let subtitle = options
+            .subtitles_enabled
+            .then(|| {
+                open_subtitle_stream(&input_context, options.subtitle_stream, video.as_ref().map(|video| video.index))
+            })
+            .flatten();
+
+        let mut media_info = MediaInfo::new(
+            &input_context,
+            video.as_ref().map(|video| video.index),
+            audio.as_ref().map(|audio| audio.index),
+        );
+        media_info.subtitle_stream = subtitle.as_ref().map(|(index, _)| *index);
+        Ok(Self { path, input_context, video, audio, subtitle, frame_duration, video_geometry, media_info })
+    }
+}
+
+// 一次加载的文件：丢弃时关闭控制通道并等待解复用线程退出，播放线程随之结束
+struct Session {
     control_sender: smol::channel::Sender<ControlCommand>,
+    // 无缝切换后由解复用线程更新
+    media_info: Arc<Mutex<MediaInfo>>,
+    // 预加载的下一个文件，解复用线程读完当前文件时取走
+    preloaded: Arc<Mutex<Option<PreparedMedia>>>,
+    // 每次预加载或取消预加载时加一，预加载线程打开文件后据此丢弃过时的结果
+    preload_generation: Arc<AtomicU64>,
     demuxer_thread: Option<std::thread::JoinHandle<()>>,
-    playing: bool,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.control_sender.close();
+        if let Some(demuxer_thread) = self.demuxer_thread.take() {
+            println!("等待解码线程结束");
+            demuxer_thread.join().unwrap();
+        }
+    }
+}
+
+// 视频帧回调在先后加载的文件之间共用
+type SharedFrameCallback = Arc<Mutex<Box<dyn FnMut(&ffmpeg::util::frame::Video) + Send>>>;
+
+pub struct Player {
+    options: PlayerOptions,
+    master_clock: Arc<MasterClock>,
+    video_statistics: Arc<video::VideoStatistics>,
+    // 视频滤镜设置，视频线程据此重建滤镜图，换文件后仍然有效
+    video_filter: Arc<video::VideoFilterSettings>,
+    event_sender: smol::channel::Sender<PlayerEvent>,
+    event_receiver: smol::channel::Receiver<PlayerEvent>,
+    error_callback: ErrorCallback,
+    video_frame_callback: SharedFrameCallback,
+    volume_control: Arc<audio::VolumeControl>,
+    // 音频滤镜设置，音频线程据此重建滤镜图，换文件后仍然有效
+    audio_filter: Arc<audio::AudioFilterSettings>,
+    // 停止或换文件后保留的音频输出，不用重新打开设备
+    audio_output: Arc<audio::AudioOutputSlot>,
+    // 当前字幕轨，解复用线程写入，active_subtitles 读取
+    subtitles: Arc<Mutex<SubtitleTrack>>,
+    // 停止状态下为 None
+    session: Option<Session>,
+    state: Arc<Mutex<PlayerState>>,
+    loop_settings: Arc<Mutex<LoopSettings>>,
+    volume: f32,
+    muted: bool,
+    rate: f64,
     playing_changed_callback: Box<dyn Fn(bool)>,
 }
 
 impl Player {
+    // 创建播放器并开始播放 path
     pub fn start(
         path: PathBuf,
+        options: PlayerOptions,
+        video_frame_callback: impl FnMut(&ffmpeg::util::frame::Video) + Send + 'static,
+        playing_changed_callback: impl Fn(bool) + 'static,
+        error_callback: impl Fn(PlayerError) + Send + Sync + 'static,
+    ) -> Result<Self, PlayerError> {
+        let mut player = Self::new(options, video_frame_callback, playing_changed_callback, error_callback);
+        player.load(path)?;
+        Ok(player)
+    }
+
+    // 创建处于停止状态的播放器，之后用 load 打开文件
+    pub fn new(
+        options: PlayerOptions,
         video_frame_callback: impl FnMut(&ffmpeg::util::frame::Video) + Send + 'static,
         playing_changed_callback: impl Fn(bool) + 'static,
-    ) -> Result<Self, anyhow::Error> {
-        println!("开始播放视频文件: {:?}", path);
+        error_callback: impl Fn(PlayerError) + Send + Sync + 'static,
+    ) -> Self {
+        let (event_sender, event_receiver) = smol::channel::bounded(64);
+        // 错误除了交给回调，也作为事件发出
+        let error_callback: ErrorCallback = {
+            let event_sender = event_sender.clone();
+            Arc::new(move |error: PlayerError| {
+                send_event(&event_sender, PlayerEvent::Error(error.clone()));
+                error_callback(error);
+            })
+        };
+
+        Self {
+            master_clock: Arc::new(MasterClock::new(options.sync_mode)),
+            video_statistics: Arc::default(),
+            video_filter: Arc::new(video::VideoFilterSettings::new(
+                &options.video_filter,
+                options.deinterlace,
+                options.autorotate,
+            )),
+            event_sender,
+            event_receiver,
+            error_callback,
+            video_frame_callback: Arc::new(Mutex::new(Box::new(video_frame_callback))),
+            volume_control: Arc::new(audio::VolumeControl::new(options.volume, options.muted)),
+            audio_filter: Arc::new(audio::AudioFilterSettings::new(&options.audio_filter)),
+            audio_output: Arc::default(),
+            subtitles: Arc::default(),
+            session: None,
+            state: Arc::new(Mutex::new(PlayerState::Stopped)),
+            loop_settings: Arc::new(Mutex::new(LoopSettings { mode: options.loop_mode, ..LoopSettings::default() })),
+            volume: audio::clamp_volume(options.volume),
+            muted: options.muted,
+            rate: 1.0,
+            playing_changed_callback: Box::new(playing_changed_callback),
+            options,
+        }
+    }
+
+    // 关闭当前文件并开始播放 path，音频输出和视频帧回调沿用
+    pub fn load(&mut self, path: PathBuf) -> Result<(), PlayerError> {
+        self.load_from(path, None)
+    }
+
+    // 和 load 一样，但从 start 处开始播放：读第一个数据包之前就跳转，不会先显示开头的画面
+    pub fn load_from(&mut self, path: PathBuf, start: Option<Duration>) -> Result<(), PlayerError> {
+        self.stop();
+        println!("开始播放视频文件: {:?}, 起点: {:?}", path, start);
+        self.set_state(PlayerState::Loading);
+        match self.open(path, start) {
+            Ok(session) => {
+                self.session = Some(session);
+                self.set_state(PlayerState::Playing);
+                (self.playing_changed_callback)(true);
+                Ok(())
+            }
+            Err(e) => {
+                self.set_state(PlayerState::Stopped);
+                Err(e)
+            }
+        }
+    }
+
+    // 停止播放并关闭文件，音频输出保持打开
+    pub fn stop(&mut self) {
+        let Some(session) = self.session.take() else {
+            return;
+        };
+        println!("停止播放");
+        let was_playing = matches!(self.state(), PlayerState::Playing | PlayerState::Ended);
+        drop(session);
+        self.master_clock.reset();
+        self.set_state(PlayerState::Stopped);
+        if was_playing {
+            (self.playing_changed_callback)(false);
+        }
+    }
+
+    // 打开文件并启动解复用和播放线程
+    fn open(&mut self, path: PathBuf, start: Option<Duration>) -> Result<Session, PlayerError> {
         let (control_sender, control_receiver) = smol::channel::unbounded();
 
+        println!("初始化输入上下文");
+        let mut input_context = ffmpeg::format::input(&path)
+            .map_err(|source| PlayerError::Open { path: path.clone(), source })?;
+
+        self.video_statistics = Arc::default();
+        let video_statistics = self.video_statistics.clone();
+        let master_clock = self.master_clock.clone();
+        master_clock.reset();
+        master_clock.set_paused(false);
+        let options = &self.options;
+        let event_sender = self.event_sender.clone();
+        let error_callback = self.error_callback.clone();
+        let state = self.state.clone();
+        let loop_settings = self.loop_settings.clone();
+        loop_settings.lock().unwrap().reset_for_new_file();
+        let video_frame_callback = self.video_frame_callback.clone();
+        // 某一路流不可用时先记下错误，另一路还能播放就继续
+        let mut stream_error = None;
+
+        println!("查找最佳视频流");
+        let video_target = match select_stream(&input_context, ffmpeg::media::Type::Video, options.video_stream) {
+            Some(video_stream) => {
+                println!("视频流索引: {}", video_stream.index());
+                match video::VideoPlaybackThread::start(
+                    &video_stream,
+                    master_clock.clone(),
+                    options.frame_drop,
+                    video_statistics.clone(),
+                    self.video_filter.clone(),
+                    error_callback.clone(),
+                    Box::new(move |frame: &ffmpeg::util::frame::Video| {
+                        let mut video_frame_callback = video_frame_callback.lock().unwrap();
+                        (*video_frame_callback)(frame)
+                    }),
+                ) {
+                    Ok(playback_thread) => Some(StreamTarget {
+                        index: Cell::new(video_stream.index()),
+                        time_base: Cell::new(f64::from(video_stream.time_base())),
+                        playback_thread,
+                    }),
+                    Err(e) => {
+                        println!("视频流不可用: {}", e);
+                        stream_error = Some(e);
+                        None
+                    }
+                }
+            }
+            None => {
+                println!("没有视频流");
+                None
+            }
+        };
+
+        println!("查找最佳音频流");
+        let audio_target = match select_stream(&input_context, ffmpeg::media::Type::Audio, options.audio_stream) {
+            Some(audio_stream) => {
+                println!("音频流索引: {}", audio_stream.index());
+                let audio_config = audio::AudioPlaybackConfig {
+                    device_selector: options.audio_device.clone(),
+                    volume_control: self.volume_control.clone(),
+                    filter_settings: self.audio_filter.clone(),
+                    downmix: options.downmix,
+                    output_slot: self.audio_output.clone(),
+                    error_callback: error_callback.clone(),
+                };
+                match audio::AudioPlaybackThread::start(
+                    &audio_stream,
+                    master_clock.clone(),
+                    &options.audio_output,
+                    audio_config,
+                    event_sender.clone(),
+                ) {
+                    Ok(playback_thread) => Some(StreamTarget {
+                        index: Cell::new(audio_stream.index()),
+                        time_base: Cell::new(f64::from(audio_stream.time_base())),
+                        playback_thread,
+                    }),
+                    Err(e) => {
+                        println!("音频流不可用: {}", e);
+                        stream_error = stream_error.or(Some(e));
+                        None
+                    }
+                }
+            }
+            None => {
+                println!("没有音频流");
+                None
+            }
+        };
+
+        if video_target.is_none() && audio_target.is_none() {
+            return Err(stream_error.unwrap_or(PlayerError::NoDecodableStream));
+        }
+        if let Some(e) = stream_error {
+            error_callback(e);
+        }
+
+        // 外挂字幕属于上一个文件，换文件时一起清掉
+        self.subtitles.lock().unwrap().reset_embedded();
+        let subtitle_stream = options
+            .subtitles_enabled
+            .then(|| {
+                open_subtitle_stream(
+                    &input_context,
+                    options.subtitle_stream,
+                    video_target.as_ref().map(|video| video.index.get()),
+                )
+            })
+            .flatten();
+
+        let mut media_info = MediaInfo::new(
+            &input_context,
+            video_target.as_ref().map(|video| video.index.get()),
+            audio_target.as_ref().map(|audio| audio.index.get()),
+        );
+        media_info.subtitle_stream = subtitle_stream.as_ref().map(|(index, _)| *index);
+        println!("媒体信息: {:?}", media_info);
+
+        // 没有音频时退回外部时钟，没有视频时退回音频时钟
+        let sync_mode = match options.sync_mode {
+            SyncMode::Audio if !media_info.has_audio => SyncMode::External,
+            SyncMode::Video if !media_info.has_video => SyncMode::Audio,
+            sync_mode => sync_mode,
+        };
+        println!("音视频同步方式: {:?}", sync_mode);
+        master_clock.set_sync_mode(sync_mode);
+
+        send_event(
+            &event_sender,
+            PlayerEvent::Opened { duration: media_info.duration, streams: media_info.clone() },
+        );
+
+        let media_info = Arc::new(Mutex::new(media_info));
+        let demuxer_media_info = media_info.clone();
+        let preloaded = Arc::new(Mutex::new(None::<PreparedMedia>));
+        let demuxer_preloaded = preloaded.clone();
+        let demuxer_master_clock = master_clock.clone();
+        let demuxer_error_callback = error_callback.clone();
+        let demuxer_subtitles = self.subtitles.clone();
         let demuxer_thread =
             std::thread::Builder::new().name("demuxer thread".into()).spawn(move || {
                 smol::block_on(async move {
-                    println!("初始化输入上下文");
-                    let mut input_context = ffmpeg::format::input(&path).unwrap();
-
-                    println!("查找最佳视频流");
-                    let video_stream =
-                        input_context.streams().best(ffmpeg::media::Type::Video).unwrap();
-                    let video_stream_index = video_stream.index();
-                    println!("视频流索引: {}", video_stream_index);
-                    let video_playback_thread = video::VideoPlaybackThread::start(
-                        &video_stream,
-                        Box::new(video_frame_callback),
-                    )
-                    .unwrap();
-
-                    println!("查找最佳音频流");
-                    let audio_stream =
-                        input_context.streams().best(ffmpeg::media::Type::Audio).unwrap();
-                    let audio_stream_index = audio_stream.index();
-                    println!("音频流索引: {}", audio_stream_index);
-                    let audio_playback_thread =
-                        audio::AudioPlaybackThread::start(&audio_stream).unwrap();
-
+                    let master_clock = demuxer_master_clock;
                     let mut playing = true;
 
+                    // 跳转和切换音轨由转发任务在两个数据包之间处理，避免与读包争用输入上下文
+                    let (request_sender, request_receiver) =
+                        smol::channel::unbounded::<ForwarderRequest>();
+                    // 指定了起点时先排一个跳转请求，转发任务读第一个数据包之前就会处理
+                    if let Some(start) = start {
+                        let _ = request_sender.try_send(ForwarderRequest::Seek(SeekRequest {
+                            target: SeekTarget::Requested(SeekPosition::Absolute(start)),
+                            flags: SeekFlags::Accurate,
+                            step: false,
+                        }));
+                    }
+                    // 文件第一个时间戳的秒数，MPEG-TS、HLS 等不从 0 开始
+                    let file_start = Cell::new(file_start_seconds(&input_context));
+                    // 最近一次读到的数据包时间，主时钟尚未开始时作为相对跳转的基准
+                    let last_position = Cell::new(0.0f64);
+                    // 文件是否已经读完，跳转后重新开始读
+                    let end_of_file = Cell::new(false);
+                    // 单帧步进期间不转发音频，继续播放时再跳转一次把音频对齐
+                    let stepping = Cell::new(false);
+                    let mut end_of_stream_reported = false;
+                    let mut buffering = false;
+                    let mut ticker = smol::Timer::interval(EVENT_TICK_INTERVAL);
+                    // 字幕包直接在转发任务里解码，解出的字幕按时间排进字幕轨
+                    let mut subtitle_stream = subtitle_stream;
+
                     let packet_forwarder_impl = async {
                         // println!("开始转发数据包");
-                        for (stream, packet) in input_context.packets() {
-                            if stream.index() == audio_stream_index {
+                        loop {
+                            // 读完文件后等待跳转请求，否则只检查一下有没有待处理的请求
+                            let request = if end_of_file.get() {
+                                match request_receiver.recv().await {
+                                    Ok(request) => Some(request),
+                                    Err(_) => break,
+                                }
+                            } else {
+                                request_receiver.try_recv().ok()
+                            };
+
+                            if let Some(ForwarderRequest::SelectAudioStream(index)) = request {
+                                let Some(audio) = &audio_target else {
+                                    println!("没有音频播放线程, 不能切换音轨");
+                                    continue;
+                                };
+                                if audio.index.get() == index {
+                                    println!("已经在播放音频流 {}", index);
+                                    continue;
+                                }
+                                let selected = input_context.stream(index).map(|stream| {
+                                    audio::open_decoder(&stream).map(|decoder| (decoder, stream.time_base()))
+                                });
+                                let (decoder, time_base) = match selected {
+                                    Some(Ok(selected)) => selected,
+                                    Some(Err(e)) => {
+                                        println!("音频流 {} 不可用: {}", index, e);
+                                        demuxer_error_callback(e);
+                                        continue;
+                                    }
+                                    None => {
+                                        println!("没有音频流 {}", index);
+                                        continue;
+                                    }
+                                };
+
+                                // 新音轨从当前播放位置开始读，视频也跟着重新定位，音频输出不关闭
+                                let position = master_clock.get().unwrap_or(last_position.get()).max(0.0);
+                                let timestamp = (position * ffmpeg::ffi::AV_TIME_BASE as f64) as i64;
+                                if let Err(e) = input_context.seek(timestamp, ..timestamp) {
+                                    println!("切换音轨时跳转失败: {}", e);
+                                    continue;
+                                }
+                                println!("切换到音频流 {}, 从 {:.3} 秒继续", index, position);
+                                audio.index.set(index);
+                                audio.time_base.set(f64::from(time_base));
+                                audio.playback_thread.switch_track(decoder, time_base, Some(position)).await;
+                                if let Some(video) = &video_target {
+                                    video.playback_thread.flush(Some(position)).await;
+                                }
+                                master_clock.external().reset();
+                                last_position.set(position);
+                                end_of_file.set(false);
+                                demuxer_media_info.lock().unwrap().audio_stream = Some(index);
+                                send_event(&event_sender, PlayerEvent::AudioStreamChanged(index));
+                                change_state(&state, Some(PlayerState::Ended), PlayerState::Playing, &event_sender);
+                                continue;
+                            }
+
+                            if let Some(ForwarderRequest::SelectSubtitleStream(index)) = request {
+                                subtitle_stream = index.and_then(|index| {
+                                    let video_stream = video_target.as_ref().map(|video| video.index.get());
+                                    open_subtitle_stream(&input_context, Some(index), video_stream)
+                                        .filter(|(selected, _)| *selected == index)
+                                });
+                                let mut subtitles = demuxer_subtitles.lock().unwrap();
+                                match (&subtitle_stream, index) {
+                                    (Some(_), _) => subtitles.reset_embedded(),
+                                    // 关闭内嵌字幕不影响已经加载的外挂字幕
+                                    (None, None) => subtitles.clear_embedded(),
+                                    (None, Some(index)) => println!("字幕流 {} 不可用, 关闭字幕", index),
+                                }
+                                let selected = subtitle_stream.as_ref().map(|(index, _)| *index);
+                                println!("字幕流: {:?}", selected);
+                                demuxer_media_info.lock().unwrap().subtitle_stream = selected;
+                                continue;
+                            }
+
+                            if let Some(ForwarderRequest::Seek(SeekRequest { target, flags, step })) = request {
+                                let target = match target {
+                                    // 绝对位置从文件第一个时间戳算起
+                                    SeekTarget::Requested(SeekPosition::Absolute(position)) => {
+                                        position.as_secs_f64() + file_start.get()
+                                    }
+                                    SeekTarget::Requested(SeekPosition::Relative(offset)) => {
+                                        master_clock.get().unwrap_or(last_position.get()) + offset
+                                    }
+                                    SeekTarget::MediaTime(time) => time,
+                                }
+                                .max(file_start.get());
+                                println!("跳转到 {:.3} 秒, 方式: {:?}", target, flags);
+
+                                let timestamp = (target * ffmpeg::ffi::AV_TIME_BASE as f64) as i64;
+                                if let Err(e) = input_context.seek(timestamp, ..timestamp) {
+                                    println!("跳转失败: {}", e);
+                                    continue;
+                                }
+
+                                let accurate_target = match flags {
+                                    SeekFlags::Keyframe => None,
+                                    SeekFlags::Accurate => Some(target),
+                                };
+                                if let Some(video) = &video_target {
+                                    video.playback_thread.flush(accurate_target).await;
+                                }
+                                if let Some(audio) = &audio_target {
+                                    audio.playback_thread.flush(accurate_target).await;
+                                }
+                                if let Some((_, decoder)) = &mut subtitle_stream {
+                                    decoder.flush();
+                                }
+                                demuxer_subtitles.lock().unwrap().clear_embedded();
+                                master_clock.external().reset();
+                                last_position.set(target);
+                                end_of_file.set(false);
+                                send_event(&event_sender, PlayerEvent::Seeked(Duration::from_secs_f64(target)));
+                                change_state(&state, Some(PlayerState::Ended), PlayerState::Playing, &event_sender);
+                                // 清空消息已经在前面，步进只会显示跳转后的帧
+                                if step {
+                                    if let Some(video) = &video_target {
+                                        video.playback_thread.send_control_message(ControlCommand::StepForward).await;
+                                    }
+                                }
+                                continue;
+                            }
+
+                            let next_packet =
+                                input_context.packets().next().map(|(stream, packet)| (stream.index(), packet));
+                            let Some((stream_index, packet)) = next_packet else {
+                                // 循环播放时跳回起点，播放线程解完剩余的数据后接着播放
+                                let loop_start = loop_settings.lock().unwrap().next_loop_start(file_start.get());
+                                if let Some((start, accurate)) = loop_start {
+                                    if restart_from(&mut input_context, &video_target, &audio_target, start, accurate).await {
+                                        master_clock.external().reset();
+                                        last_position.set(start);
+                                        send_event(&event_sender, PlayerEvent::Looped(Duration::from_secs_f64(start)));
+                                        continue;
+                                    }
+                                }
+
+                                // 有预加载的下一个文件时接着读它，播放线程换上新的解码器后继续输出
+                                let next_media = demuxer_preloaded.lock().unwrap().take();
+                                if let Some(next_media) = next_media {
+                                    if next_media.video.is_some() == video_target.is_some()
+                                        && next_media.audio.is_some() == audio_target.is_some()
+                                    {
+                                        println!("无缝切换到: {:?}", next_media.path);
+                                        input_context = next_media.input_context;
+                                        file_start.set(file_start_seconds(&input_context));
+                                        if let (Some(video), Some(next_video)) = (&video_target, next_media.video) {
+                                            video.index.set(next_video.index);
+                                            video.time_base.set(f64::from(next_video.time_base));
+                                            video
+                                                .playback_thread
+                                                .switch_decoder(
+                                                    next_video.decoder,
+                                                    next_video.time_base,
+                                                    next_media.frame_duration,
+                                                    next_media.video_geometry,
+                                                )
+                                                .await;
+                                        }
+                                        if let (Some(audio), Some(next_audio)) = (&audio_target, next_media.audio) {
+                                            audio.index.set(next_audio.index);
+                                            audio.time_base.set(f64::from(next_audio.time_base));
+                                            audio.playback_thread.switch_decoder(next_audio.decoder, next_audio.time_base).await;
+                                        }
+                                        // 上一个文件的字幕还没显示完的就不显示了
+                                        subtitle_stream = next_media.subtitle;
+                                        demuxer_subtitles.lock().unwrap().reset_embedded();
+                                        master_clock.external().reset();
+                                        last_position.set(0.0);
+                                        loop_settings.lock().unwrap().reset_for_new_file();
+                                        let media_info = next_media.media_info;
+                                        *demuxer_media_info.lock().unwrap() = media_info.clone();
+                                        send_event(&event_sender, PlayerEvent::PreloadedStarted { path: next_media.path });
+                                        send_event(
+                                            &event_sender,
+                                            PlayerEvent::Opened { duration: media_info.duration, streams: media_info },
+                                        );
+                                        continue;
+                                    }
+                                    println!("预加载的文件与当前文件的音视频流组成不同, 不能无缝切换");
+                                }
+
+                                // println!("数据包转发完成");
+                                // 通知播放线程冲刷解码器，把最后几帧也播出来
+                                if let Some(video) = &video_target {
+                                    video.playback_thread.end_of_stream().await;
+                                }
+                                if let Some(audio) = &audio_target {
+                                    audio.playback_thread.end_of_stream().await;
+                                }
+                                end_of_file.set(true);
+                                continue;
+                            };
+
+                            // A-B 循环：读到终点之后的数据包就跳回起点，步进时不处理
+                            let ab_repeat = loop_settings.lock().unwrap().ab_repeat.filter(|_| !stepping.get());
+                            if let (Some((start, end)), Some(pts)) = (ab_repeat, packet.pts()) {
+                                let time_base = audio_target
+                                    .as_ref()
+                                    .filter(|audio| audio.index.get() == stream_index)
+                                    .map(|audio| audio.time_base.get())
+                                    .or_else(|| {
+                                        video_target
+                                            .as_ref()
+                                            .filter(|video| video.index.get() == stream_index)
+                                            .map(|video| video.time_base.get())
+                                    });
+                                if time_base.is_some_and(|time_base| pts as f64 * time_base >= end)
+                                    && restart_from(&mut input_context, &video_target, &audio_target, start, true).await
+                                {
+                                    println!("A-B 循环回到 {:.3} 秒", start);
+                                    master_clock.external().reset();
+                                    last_position.set(start);
+                                    send_event(&event_sender, PlayerEvent::Looped(Duration::from_secs_f64(start)));
+                                    continue;
+                                }
+                            }
+
+                            if let Some((_, decoder)) = subtitle_stream.as_mut().filter(|(index, _)| *index == stream_index) {
+                                match decoder.decode(&packet) {
+                                    Ok(Some((start, end, content))) => {
+                                        let mut subtitles = demuxer_subtitles.lock().unwrap();
+                                        // 加载了外挂字幕后不再写入内嵌字幕
+                                        if !subtitles.is_external() {
+                                            subtitles.push(start, end, content);
+                                        }
+                                    }
+                                    Ok(None) => {}
+                                    Err(e) => println!("字幕解码失败: {}", e),
+                                }
+                                continue;
+                            }
+
+                            if let Some(audio) = audio_target.as_ref().filter(|audio| audio.index.get() == stream_index) {
                                 // println!("转发音频");
-                                audio_playback_thread.receive_packet(packet).await;
-                            } else if stream.index() == video_stream_index {
+                                if stepping.get() {
+                                    continue;
+                                }
+                                if let Some(pts) = packet.pts() {
+                                    last_position.set(pts as f64 * audio.time_base.get());
+                                }
+                                audio.playback_thread.receive_packet(packet).await;
+                            } else if let Some(video) = video_target.as_ref().filter(|video| video.index.get() == stream_index) {
                                 // println!("转发视频包");
-                                video_playback_thread.receive_packet(packet).await;
+                                if let Some(pts) = packet.pts() {
+                                    last_position.set(pts as f64 * video.time_base.get());
+                                }
+                                video.playback_thread.receive_packet(packet).await;
                             }
                         }
-                        // println!("数据包转发完成");
                     }
                     .fuse()
                     .shared();
 
                     loop {
-                        let packet_forwarder: OptionFuture<_> =
-                            if playing { Some(packet_forwarder_impl.clone()) } else { None }.into();
+                        // 暂停时播放线程不再消费，有界队列填满后转发自然会停下；
+                        // 这里始终轮询转发任务，以便暂停状态下也能处理跳转
+                        let packet_forwarder = packet_forwarder_impl.clone();
 
                         smol::pin!(packet_forwarder);
 
@@ -82,20 +1092,165 @@ impl Player {
                             _ = packet_forwarder => {
                                 // println!("播放器播放完成");
                             },
+                            _ = ticker.next().fuse() => {
+                                if playing {
+                                    if let Some(position) = master_clock.get() {
+                                        send_event(&event_sender, PlayerEvent::Position(Duration::from_secs_f64(position.max(0.0))));
+                                    }
+
+                                    let ab_restart = loop_settings.lock().unwrap().ab_restart_due(master_clock.get(), last_position.get());
+                                    if let (Some(start), false) = (ab_restart, stepping.get()) {
+                                        println!("播放到 A-B 循环终点, 清空队列回到 {:.3} 秒", start);
+                                        discard_all_queued_packets(&video_target, &audio_target);
+                                        // 跳转处理完之前不再重复触发
+                                        last_position.set(start);
+                                        let _ = request_sender
+                                            .send(ForwarderRequest::Seek(SeekRequest {
+                                                target: SeekTarget::MediaTime(start),
+                                                flags: SeekFlags::Accurate,
+                                                step: false,
+                                            }))
+                                            .await;
+                                        send_event(&event_sender, PlayerEvent::Looped(Duration::from_secs_f64(start)));
+                                    }
+                                }
+
+                                if end_of_file.get() {
+                                    let video_finished = video_target.as_ref().map_or(true, |video| video.playback_thread.is_finished());
+                                    let audio_finished = audio_target.as_ref().map_or(true, |audio| audio.playback_thread.is_finished());
+                                    if video_finished && audio_finished && !end_of_stream_reported {
+                                        println!("播放结束");
+                                        end_of_stream_reported = true;
+                                        send_event(&event_sender, PlayerEvent::EndOfStream);
+                                        change_state(&state, Some(PlayerState::Playing), PlayerState::Ended, &event_sender);
+                                    }
+                                    if buffering {
+                                        buffering = false;
+                                        send_event(&event_sender, PlayerEvent::Buffering(100));
+                                    }
+                                } else {
+                                    end_of_stream_reported = false;
+                                    if playing {
+                                        let buffer_percent = video_target
+                                            .iter()
+                                            .map(|video| video.playback_thread.buffer_percent())
+                                            .chain(audio_target.iter().map(|audio| audio.playback_thread.buffer_percent()))
+                                            .max()
+                                            .unwrap_or(100);
+                                        if buffer_percent < BUFFERING_THRESHOLD_PERCENT {
+                                            buffering = true;
+                                            send_event(&event_sender, PlayerEvent::Buffering(buffer_percent));
+                                        } else if buffering {
+                                            buffering = false;
+                                            send_event(&event_sender, PlayerEvent::Buffering(100));
+                                        }
+                                    }
+                                }
+                            },
                             received_command = control_receiver.recv().fuse() => {
                                 match received_command {
                                     Ok(command) => {
                                         println!("收到控制命令: {:?}", command);
-                                        video_playback_thread.send_control_message(command).await;
-                                        audio_playback_thread.send_control_message(command).await;
                                         match command {
                                             ControlCommand::Play => {
                                                 println!("继续播放");
                                                 playing = true;
+                                                if stepping.replace(false) {
+                                                    // 步进期间音频包都被丢掉了，从当前画面处重新开始
+                                                    let position = video_target
+                                                        .as_ref()
+                                                        .and_then(|video| video.playback_thread.last_frame_time())
+                                                        .or(master_clock.get())
+                                                        .unwrap_or(last_position.get());
+                                                    println!("结束单帧步进, 从 {:.3} 秒继续", position);
+                                                    discard_all_queued_packets(&video_target, &audio_target);
+                                                    let _ = request_sender
+                                                        .send(ForwarderRequest::Seek(SeekRequest {
+                                                            target: SeekTarget::MediaTime(position),
+                                                            flags: SeekFlags::Accurate,
+                                                            step: false,
+                                                        }))
+                                                        .await;
+                                                }
+                                                forward_control_message(&video_target, &audio_target, command).await;
+                                                master_clock.set_paused(false);
                                             },
                                             ControlCommand::Pause => {
                                                 println!("暂停播放");
                                                 playing = false;
+                                                forward_control_message(&video_target, &audio_target, command).await;
+                                                master_clock.set_paused(true);
+                                            }
+                                            ControlCommand::Seek { position, flags } => {
+                                                println!("请求跳转, 当前{}", if playing { "播放中" } else { "已暂停" });
+                                                // 先清掉队列里的旧数据包，让阻塞中的转发任务尽快处理跳转
+                                                discard_all_queued_packets(&video_target, &audio_target);
+                                                let _ = request_sender
+                                                    .send(ForwarderRequest::Seek(SeekRequest {
+                                                        target: SeekTarget::Requested(position),
+                                                        flags,
+                                                        step: false,
+                                                    }))
+                                                    .await;
+                                            }
+                                            ControlCommand::SelectAudioStream(index) => {
+                                                println!("请求切换到音频流 {}", index);
+                                                // 和跳转一样先清掉队列，切换后会从当前位置重新读
+                                                discard_all_queued_packets(&video_target, &audio_target);
+                                                let _ = request_sender.send(ForwarderRequest::SelectAudioStream(index)).await;
+                                            }
+                                            ControlCommand::SelectSubtitleStream(index) => {
+                                                println!("请求切换到字幕流 {:?}", index);
+                                                let _ = request_sender.send(ForwarderRequest::SelectSubtitleStream(index)).await;
+                                                // 已经读过的数据包里没有新字幕流的字幕，从当前位置重新读一遍
+                                                if index.is_some() {
+                                                    let position = master_clock.get().unwrap_or(last_position.get());
+                                                    discard_all_queued_packets(&video_target, &audio_target);
+                                                    let _ = request_sender
+                                                        .send(ForwarderRequest::Seek(SeekRequest {
+                                                            target: SeekTarget::MediaTime(position),
+                                                            flags: SeekFlags::Accurate,
+                                                            step: false,
+                                                        }))
+                                                        .await;
+                                                }
+                                            }
+                                            ControlCommand::StepForward | ControlCommand::StepBackward if playing => {
+                                                println!("播放中忽略单帧步进");
+                                            }
+                                            ControlCommand::StepForward | ControlCommand::StepBackward => {
+                                                let Some(video) = &video_target else {
+                                                    println!("没有视频流, 忽略单帧步进");
+                                                    continue;
+                                                };
+                                                if !stepping.replace(true) {
+                                                    // 步进期间位置跟随画面
+                                                    println!("进入单帧步进");
+                                                    master_clock.audio().reset();
+                                                    if let Some(audio) = &audio_target {
+                                                        audio.playback_thread.discard_queued_packets();
+                                                    }
+                                                }
+                                                if let ControlCommand::StepForward = command {
+                                                    video.playback_thread.send_control_message(command).await;
+                                                } else {
+                                                    // 解码到上一帧：早于它的帧都丢弃，第一张不早于它的就是上一帧
+                                                    let current = video
+                                                        .playback_thread
+                                                        .last_frame_time()
+                                                        .or(master_clock.get())
+                                                        .unwrap_or(last_position.get());
+                                                    let target = current - video.playback_thread.frame_duration() * 1.5;
+                                                    println!("单帧后退, 当前 {:.3} 秒, 目标 {:.3} 秒", current, target);
+                                                    video.playback_thread.discard_queued_packets();
+                                                    let _ = request_sender
+                                                        .send(ForwarderRequest::Seek(SeekRequest {
+                                                            target: SeekTarget::MediaTime(target),
+                                                            flags: SeekFlags::Accurate,
+                                                            step: true,
+                                                        }))
+                                                        .await;
+                                                }
                                             }
                                         }
                                     }
@@ -110,38 +1265,442 @@ impl Player {
                 })
             })?;
 
-        let playing = true;
-        playing_changed_callback(playing);
-
-        Ok(Self {
+        Ok(Session {
             control_sender,
+            media_info,
+            preloaded,
+            preload_generation: Arc::new(AtomicU64::new(0)),
             demuxer_thread: Some(demuxer_thread),
-            playing,
-            playing_changed_callback: Box::new(playing_changed_callback),
         })
     }
 
+    pub fn state(&self) -> PlayerState {
+        *self.state.lock().unwrap()
+    }
+
+    fn set_state(&self, new_state: PlayerState) {
+        change_state(&self.state, None, new_state, &self.event_sender);
+    }
+
+    // 播放结束后再切换会从头开始播放
     pub fn toggle_pause_playing(&mut self) {
-        if self.playing {
-            println!("切换到暂停状态");
-            self.playing = false;
-            self.control_sender.send_blocking(ControlCommand::Pause).unwrap();
+        match self.state() {
+            PlayerState::Playing => {
+                println!("切换到暂停状态");
+                self.set_state(PlayerState::Paused);
+                self.send_command(ControlCommand::Pause);
+                (self.playing_changed_callback)(false);
+            }
+            PlayerState::Paused => {
+                println!("切换到播放状态");
+                self.set_state(PlayerState::Playing);
+                self.send_command(ControlCommand::Play);
+                (self.playing_changed_callback)(true);
+            }
+            PlayerState::Ended => {
+                println!("播放已结束, 从头开始");
+                self.seek(SeekPosition::Absolute(Duration::ZERO), SeekFlags::Keyframe);
+            }
+            state => println!("{:?} 状态下不能切换暂停", state),
+        }
+    }
+
+    // 设置音量，1.0 为原始音量，超出 0.0..=2.0 的值会被截断
+    pub fn set_volume(&mut self, volume: f32) {
+        // clamp 会让 NaN 原样通过，输出的每个采样都会变成 NaN
+        if !volume.is_finite() {
+            println!("音量 {} 无效, 忽略", volume);
+            return;
+        }
+        self.volume = volume.clamp(0.0, audio::MAX_VOLUME);
+        println!("设置音量: {:.0}%", self.volume * 100.0);
+        self.volume_control.set_volume(self.volume);
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        println!("设置静音: {}", muted);
+        self.muted = muted;
+        self.volume_control.set_muted(muted);
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.set_muted(!self.muted);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    // 设置音频滤镜，如均衡、响度归一化、动态范围压缩，正在播放时下一帧起生效；
+    // 滤镜有误时通过错误回调报告，声音不经过滤镜
+    pub fn set_audio_filter(&mut self, description: &str) {
+        println!("设置音频滤镜: {:?}", description);
+        self.audio_filter.set_description(description);
+    }
+
+    pub fn audio_filter(&self) -> String {
+        self.audio_filter.description()
+    }
+
+    // 设置播放倍速，音频变速不变调，超出 MIN_RATE..=MAX_RATE 的值会被截断
+    pub fn set_rate(&mut self, rate: f64) {
+        if !rate.is_finite() {
+            println!("播放倍速 {} 无效, 忽略", rate);
+            return;
+        }
+        self.rate = rate.clamp(MIN_RATE, MAX_RATE);
+        println!("设置播放倍速: {}x", self.rate);
+        // 视频线程和音频变速滤镜都直接读主时钟的倍速，停止状态下设置也对之后加载的文件生效
+        self.master_clock.set_speed(self.rate);
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    // 设置视频滤镜，下一帧起按新的滤镜图处理；滤镜有误时通过错误回调报告，画面不经过滤镜
+    pub fn set_video_filter(&mut self, description: &str) {
+        println!("设置视频滤镜: {:?}", description);
+        self.video_filter.set_description(description);
+    }
+
+    pub fn video_filter(&self) -> String {
+        self.video_filter.description()
+    }
+
+    pub fn set_deinterlace(&mut self, deinterlace: bool) {
+        println!("设置去隔行: {}", deinterlace);
+        self.video_filter.set_deinterlace(deinterlace);
+    }
+
+    pub fn toggle_deinterlace(&mut self) {
+        self.set_deinterlace(!self.is_deinterlacing());
+    }
+
+    pub fn is_deinterlacing(&self) -> bool {
+        self.video_filter.deinterlace()
+    }
+
+    // 整个文件的循环方式，换文件后仍然有效
+    pub fn set_loop_mode(&mut self, mode: LoopMode) {
+        println!("设置循环方式: {:?}", mode);
+        let mut loop_settings = self.loop_settings.lock().unwrap();
+        loop_settings.mode = mode;
+        loop_settings.completed = 0;
+    }
+
+    pub fn loop_mode(&self) -> LoopMode {
+        self.loop_settings.lock().unwrap().mode
+    }
+
+    // 在 start 和 end 之间反复播放，优先于整个文件的循环；end 不晚于 start 时忽略
+    pub fn set_ab_repeat(&mut self, start: Duration, end: Duration) {
+        if end <= start {
+            println!("A-B 循环终点 {:?} 不晚于起点 {:?}, 忽略", end, start);
+            return;
+        }
+        println!("设置 A-B 循环: {:?} - {:?}", start, end);
+        self.loop_settings.lock().unwrap().ab_repeat = Some((start.as_secs_f64(), end.as_secs_f64()));
+    }
+
+    pub fn clear_ab_repeat(&mut self) {
+        println!("取消 A-B 循环");
+        self.loop_settings.lock().unwrap().ab_repeat = None;
+    }
+
+    pub fn ab_repeat(&self) -> Option<(Duration, Duration)> {
+        self.loop_settings
+            .lock()
+            .unwrap()
+            .ab_repeat
+            .map(|(start, end)| (Duration::from_secs_f64(start), Duration::from_secs_f64(end)))
+    }
+
+    // 播放中换用另一条音轨，音频输出不重新打开
+    pub fn select_audio_stream(&mut self, index: usize) -> Result<(), PlayerError> {
+        let Some(media_info) = self.media_info() else {
+            println!("没有加载文件, 忽略切换音轨");
+            return Ok(());
+        };
+        let is_audio = media_info
+            .streams
+            .iter()
+            .any(|stream| stream.index == index && stream.media_type == ffmpeg::media::Type::Audio);
+        if !is_audio {
+            return Err(PlayerError::InvalidStream { stream_index: index, media_type: ffmpeg::media::Type::Audio });
+        }
+        self.send_command(ControlCommand::SelectAudioStream(index));
+        Ok(())
+    }
+
+    // 换用另一路内嵌字幕流，None 关闭字幕，包括已加载的外挂字幕
+    pub fn select_subtitle_stream(&mut self, index: Option<usize>) -> Result<(), PlayerError> {
+        let Some(media_info) = self.media_info() else {
+            println!("没有加载文件, 忽略切换字幕");
+            return Ok(());
+        };
+        if let Some(index) = index {
+            let is_subtitle = media_info
+                .streams
+                .iter()
+                .any(|stream| stream.index == index && stream.media_type == ffmpeg::media::Type::Subtitle);
+            if !is_subtitle {
+                return Err(PlayerError::InvalidStream { stream_index: index, media_type: ffmpeg::media::Type::Subtitle });
+            }
         } else {
-            println!("切换到播放状态");
-            self.playing = true;
-            self.control_sender.send_blocking(ControlCommand::Play).unwrap();
+            self.subtitles.lock().unwrap().reset_embedded();
+        }
+        self.send_command(ControlCommand::SelectSubtitleStream(index));
+        Ok(())
+    }
+
+    // 加载外挂字幕文件，替换当前显示的字幕，换文件后失效
+    pub fn load_subtitle_file(&mut self, path: &Path) -> Result<(), PlayerError> {
+        if self.session.is_none() {
+            println!("没有加载文件, 忽略外挂字幕: {:?}", path);
+            return Ok(());
+        }
+        let track = subtitle::read_subtitle_file(path)?;
+        self.subtitles.lock().unwrap().replace_with_external(track);
+        // 内嵌字幕不再解码
+        self.send_command(ControlCommand::SelectSubtitleStream(None));
+        Ok(())
+    }
+
+    // 字幕延迟秒数，正数表示字幕晚出现，换文件后仍然有效
+    pub fn set_subtitle_delay(&mut self, delay: f64) {
+        println!("设置字幕延迟: {:.3} 秒", delay);
+        self.subtitles.lock().unwrap().set_delay(delay);
+    }
+
+    pub fn subtitle_delay(&self) -> f64 {
+        self.subtitles.lock().unwrap().delay()
+    }
+
+    // 按主时钟当前时间应该显示的字幕
+    pub fn active_subtitles(&self) -> Vec<Arc<SubtitleCue>> {
+        match self.master_clock.get() {
+            Some(time) if self.session.is_some() => self.subtitles.lock().unwrap().active(time),
+            _ => Vec::new(),
+        }
+    }
+
+    // 文件里有哪些媒体以及总时长，停止状态下为 None
+    pub fn media_info(&self) -> Option<MediaInfo> {
+        self.session.as_ref().map(|session| session.media_info.lock().unwrap().clone())
+    }
+
+    // 预先打开下一个文件，当前文件读完时直接切换过去，音频经同一个输出连续播放。
+    // 文件在单独的线程里打开，不阻塞调用方；打开失败或者当前文件读完时还没打开好，
+    // 以及两个文件的音视频流组成不同时都不切换，照常发出 EndOfStream
+    pub fn preload(&mut self, path: PathBuf) -> Result<(), PlayerError> {
+        let Some(session) = &self.session else {
+            println!("没有加载文件, 忽略预加载: {:?}", path);
+            return Ok(());
+        };
+        println!("预加载: {:?}", path);
+        let generation = {
+            let mut preloaded = session.preloaded.lock().unwrap();
+            preloaded.take();
+            session.preload_generation.fetch_add(1, Ordering::Relaxed) + 1
+        };
+
+        let options = self.options.clone();
+        let preloaded = session.preloaded.clone();
+        let preload_generation = session.preload_generation.clone();
+        std::thread::Builder::new().name("preload thread".into()).spawn(move || {
+            let next_media = match PreparedMedia::open(path.clone(), &options) {
+                Ok(next_media) => next_media,
+                Err(e) => {
+                    println!("预加载 {:?} 失败: {}", path, e);
+                    return;
+                }
+            };
+            let mut preloaded = preloaded.lock().unwrap();
+            // 打开期间又预加载了别的文件或者取消了预加载
+            if preload_generation.load(Ordering::Relaxed) != generation {
+                println!("丢弃过时的预加载: {:?}", path);
+                return;
+            }
+            *preloaded = Some(next_media);
+        })?;
+        Ok(())
+    }
+
+    pub fn cancel_preload(&mut self) {
+        if let Some(session) = &self.session {
+            let mut preloaded = session.preloaded.lock().unwrap();
+            session.preload_generation.fetch_add(1, Ordering::Relaxed);
+            if preloaded.take().is_some() {
+                println!("取消预加载");
+            }
+        }
+    }
+
+    // 播放器事件通道。通道是多消费者的，多个接收端会分摊同一份事件
+    pub fn events(&self) -> smol::channel::Receiver<PlayerEvent> {
+        self.event_receiver.clone()
+    }
+
+    // 当前播放位置，以主时钟为准；还没有开始出画面或出声时返回 None
+    pub fn position(&self) -> Option<Duration> {
+        self.master_clock.get().map(|time| Duration::from_secs_f64(time.max(0.0)))
+    }
+
+    // 视频帧的显示和丢弃统计
+    pub fn frame_statistics(&self) -> video::FrameStatistics {
+        self.video_statistics.snapshot()
+    }
+
+    pub fn seek(&mut self, position: SeekPosition, flags: SeekFlags) {
+        println!("跳转: {:?}, {:?}", position, flags);
+        self.send_command(ControlCommand::Seek { position, flags });
+    }
+
+    // 前进一帧，播放中调用会先暂停；音频在步进期间保持静默
+    pub fn step_forward(&mut self) {
+        self.pause_for_stepping();
+        self.send_command(ControlCommand::StepForward);
+    }
+
+    // 后退一帧，播放中调用会先暂停
+    pub fn step_backward(&mut self) {
+        self.pause_for_stepping();
+        self.send_command(ControlCommand::StepBackward);
+    }
+
+    fn pause_for_stepping(&mut self) {
+        if self.state() == PlayerState::Playing {
+            self.toggle_pause_playing();
+        }
+    }
+
+    fn send_command(&self, command: ControlCommand) {
+        let Some(session) = &self.session else {
+            println!("没有加载文件, 忽略控制命令: {:?}", command);
+            return;
+        };
+        if let Err(e) = session.control_sender.send_blocking(command) {
+            println!("发送控制命令失败, 解复用线程已退出: {}", e);
         }
-        (self.playing_changed_callback)(self.playing);
     }
 }
 
 impl Drop for Player {
     fn drop(&mut self) {
         println!("Player dropped");
-        self.control_sender.close();
-        if let Some(decoder_thread) = self.demuxer_thread.take() {
-            println!("等待解码线程结束");
-            decoder_thread.join().unwrap();
-        }
+        self.session.take();
+    }
+}
+
+// 丢弃所有播放线程队列中尚未解码的数据包
+fn discard_all_queued_packets(
+    video_target: &Option<StreamTarget<video::VideoPlaybackThread>>,
+    audio_target: &Option<StreamTarget<audio::AudioPlaybackThread>>,
+) {
+    if let Some(video) = video_target {
+        video.playback_thread.discard_queued_packets();
+    }
+    if let Some(audio) = audio_target {
+        audio.playback_thread.discard_queued_packets();
+    }
+}
+
+// 循环播放时跳回 start，不清空队列，播放线程解完之前的数据后接着播放
+// 文件第一个时间戳的秒数，容器没有给出时为 0
+fn file_start_seconds(input_context: &ffmpeg::format::context::Input) -> f64 {
+    let start_time = unsafe { (*input_context.as_ptr()).start_time };
+    if start_time == ffmpeg::ffi::AV_NOPTS_VALUE {
+        0.0
+    } else {
+        start_time as f64 / ffmpeg::ffi::AV_TIME_BASE as f64
+    }
+}
+
+async fn restart_from(
+    input_context: &mut ffmpeg::format::context::Input,
+    video_target: &Option<StreamTarget<video::VideoPlaybackThread>>,
+    audio_target: &Option<StreamTarget<audio::AudioPlaybackThread>>,
+    start: f64,
+    accurate: bool,
+) -> bool {
+    let timestamp = (start * ffmpeg::ffi::AV_TIME_BASE as f64) as i64;
+    if let Err(e) = input_context.seek(timestamp, ..timestamp) {
+        println!("循环跳转失败: {}", e);
+        return false;
     }
-}
\ No newline at end of file
+    let target = accurate.then_some(start);
+    if let Some(video) = video_target {
+        video.playback_thread.restart(target).await;
+    }
+    if let Some(audio) = audio_target {
+        audio.playback_thread.restart(target).await;
+    }
+    true
+}
+
+// 把播放/暂停命令转给存在的播放线程
+async fn forward_control_message(
+    video_target: &Option<StreamTarget<video::VideoPlaybackThread>>,
+    audio_target: &Option<StreamTarget<audio::AudioPlaybackThread>>,
+    command: ControlCommand,
+) {
+    if let Some(video) = video_target {
+        video.playback_thread.send_control_message(command).await;
+    }
+    if let Some(audio) = audio_target {
+        audio.playback_thread.send_control_message(command).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loop_count_restarts_until_all_passes_played() {
+        let mut loop_settings = LoopSettings { mode: LoopMode::Count(3), ..LoopSettings::default() };
+        assert_eq!(loop_settings.next_loop_start(0.0), Some((0.0, false)));
+        assert_eq!(loop_settings.next_loop_start(0.0), Some((0.0, false)));
+        assert_eq!(loop_settings.next_loop_start(0.0), None);
+        // 播完后重新计数
+        assert_eq!(loop_settings.next_loop_start(0.0), Some((0.0, false)));
+
+        // 不从 0 开始的文件跳回第一个时间戳
+        let mut loop_settings = LoopSettings { mode: LoopMode::Infinite, ..LoopSettings::default() };
+        assert_eq!(loop_settings.next_loop_start(1.4), Some((1.4, false)));
+        loop_settings.mode = LoopMode::Off;
+        assert_eq!(loop_settings.next_loop_start(0.0), None);
+    }
+
+    #[test]
+    fn ab_repeat_end_after_file_end_restarts_at_a() {
+        let mut loop_settings = LoopSettings { mode: LoopMode::Count(2), ..LoopSettings::default() };
+        loop_settings.ab_repeat = Some((5.0, 100.0));
+        assert_eq!(loop_settings.next_loop_start(0.0), Some((5.0, true)));
+        assert_eq!(loop_settings.completed, 0);
+
+        loop_settings.reset_for_new_file();
+        assert_eq!(loop_settings.ab_repeat, None);
+        assert_eq!(loop_settings.next_loop_start(0.0), Some((0.0, false)));
+    }
+
+    #[test]
+    fn ab_restart_waits_for_playback_to_reach_b() {
+        let loop_settings = LoopSettings { ab_repeat: Some((2.0, 4.0)), ..LoopSettings::default() };
+
+        // 解复用已经越过终点，播放还没到
+        assert_eq!(loop_settings.ab_restart_due(Some(3.9), 6.0), None);
+        assert_eq!(loop_settings.ab_restart_due(None, 6.0), None);
+        assert_eq!(loop_settings.ab_restart_due(Some(4.0), 6.0), Some(2.0));
+        // 解复用已经在终点处跳回起点，排队的数据包播到终点后自然回到起点
+        assert_eq!(loop_settings.ab_restart_due(Some(4.02), 2.5), None);
+
+        let loop_settings = LoopSettings::default();
+        assert_eq!(loop_settings.ab_restart_due(Some(10.0), 10.0), None);
+    }
+}
@@ -0,0 +1,633 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::PlayerError;
+use crate::player::{Player, PlayerEvent};
+
+// 播放列表的重复方式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RepeatMode {
+    // 播放到最后一项后停止
+    #[default]
+    Off,
+    // 重复当前项
+    One,
+    // 播放到最后一项后从头开始
+    All,
+}
+
+impl RepeatMode {
+    // 依次切换 Off -> All -> One -> Off
+    pub fn cycle(self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Off,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlaylistItem {
+    // 本地文件路径或 URL
+    pub path: PathBuf,
+    // 播放列表文件里给出的标题
+    pub title: Option<String>,
+}
+
+impl From<PathBuf> for PlaylistItem {
+    fn from(path: PathBuf) -> Self {
+        Self { path, title: None }
+    }
+}
+
+// 驱动 Player 按顺序播放多个文件。当前项开始播放后就预加载下一项，
+// 读完时 Player 直接切换过去，音频经同一个输出不间断播放。
+// 需要把 Player 发出的事件都交给 handle_event
+#[derive(Debug, Default)]
+pub struct Playlist {
+    items: Vec<PlaylistItem>,
+    // 播放顺序，存的是 items 的下标；不随机播放时就是 0..n
+    order: Vec<usize>,
+    // 正在播放的项
+    current: Option<usize>,
+    // 正在播放的项被移除后，下一项从播放顺序中的这个位置开始
+    resume_position: Option<usize>,
+    shuffle: bool,
+    repeat: RepeatMode,
+    // 已经交给 Player 预加载的项
+    preloaded: Option<usize>,
+    // 列表或播放方式变化后，需要重新决定预加载哪一项
+    preload_dirty: bool,
+}
+
+impl Playlist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 按扩展名判断是否为支持的播放列表文件，URL 交给 FFmpeg 打开（比如 HLS 的 .m3u8）
+    pub fn is_playlist_file(path: &Path) -> bool {
+        if path.to_str().is_some_and(is_url) {
+            return false;
+        }
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                ["m3u", "m3u8", "pls"].contains(&extension.to_ascii_lowercase().as_str())
+            })
+    }
+
+    // 读取 M3U/M3U8 或 PLS 播放列表，相对路径按播放列表所在目录解析。
+    // 本地的 HLS 播放列表是一个媒体，作为只有一项的列表交给 FFmpeg 打开
+    pub fn from_file(path: &Path) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        // 老的 .m3u 可能不是 UTF-8，无法解码的字符直接替换掉
+        let content = String::from_utf8_lossy(&bytes);
+        let content = content.trim_start_matches('\u{feff}');
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut playlist = Self::new();
+        if content.contains("#EXT-X-") {
+            println!("{:?} 是 HLS 播放列表, 直接播放", path);
+            playlist.append(path.to_path_buf());
+            return Ok(playlist);
+        }
+
+        let is_pls = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("pls"))
+            || content.trim_start().to_ascii_lowercase().starts_with("[playlist]");
+        let items = if is_pls {
+            parse_pls(content, base_dir)
+        } else {
+            parse_m3u(content, base_dir)
+        };
+        println!("读取播放列表 {:?}: {} 项", path, items.len());
+
+        for item in items {
+            playlist.append(item);
+        }
+        Ok(playlist)
+    }
+
+    pub fn items(&self) -> &[PlaylistItem] {
+        &self.items
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    // 正在播放的项在列表中的下标
+    pub fn current_index(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn current(&self) -> Option<&PlaylistItem> {
+        self.current.map(|index| &self.items[index])
+    }
+
+    pub fn append(&mut self, item: impl Into<PlaylistItem>) {
+        let index = self.items.len();
+        self.items.push(item.into());
+        if self.shuffle {
+            // 随机插到还没播放的部分里
+            let start = self.current_position().map_or(0, |position| position + 1);
+            let position = fastrand::usize(start..=self.order.len());
+            self.order.insert(position, index);
+        } else {
+            self.order.push(index);
+        }
+        self.preload_dirty = true;
+    }
+
+    // 移除一项。移除正在播放的项不会停止播放，播完后接着播放原来排在它后面的项
+    pub fn remove(&mut self, index: usize) -> Option<PlaylistItem> {
+        if index >= self.items.len() {
+            return None;
+        }
+        let item = self.items.remove(index);
+
+        let removed_position = self.order.iter().position(|&i| i == index);
+        self.order.retain(|&i| i != index);
+        for i in &mut self.order {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+
+        if let (Some(resume_position), Some(removed_position)) = (self.resume_position, removed_position) {
+            if removed_position < resume_position {
+                self.resume_position = Some(resume_position - 1);
+            }
+        }
+        match self.current {
+            Some(current) if current == index => {
+                self.current = None;
+                self.resume_position = removed_position;
+            }
+            Some(current) if current > index => self.current = Some(current - 1),
+            _ => {}
+        }
+        self.preloaded = match self.preloaded {
+            Some(preloaded) if preloaded == index => None,
+            Some(preloaded) if preloaded > index => Some(preloaded - 1),
+            preloaded => preloaded,
+        };
+        self.preload_dirty = true;
+        Some(item)
+    }
+
+    // 把 from 处的项移到 to 处，下标越界时返回 false
+    pub fn move_item(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.items.len() || to >= self.items.len() {
+            return false;
+        }
+        let item = self.items.remove(from);
+        self.items.insert(to, item);
+
+        let remap = |i: usize| {
+            if i == from {
+                to
+            } else if from < to && i > from && i <= to {
+                i - 1
+            } else if to < from && i >= to && i < from {
+                i + 1
+            } else {
+                i
+            }
+        };
+        if self.shuffle {
+            for i in &mut self.order {
+                *i = remap(*i);
+            }
+        } else {
+            // 顺序播放时播放顺序就是列表顺序
+            self.order = (0..self.items.len()).collect();
+            self.resume_position = self.resume_position.map(remap);
+        }
+        self.current = self.current.map(remap);
+        self.preloaded = self.preloaded.map(remap);
+        self.preload_dirty = true;
+        true
+    }
+
+    // 打开随机播放时打乱剩余的顺序，正在播放的项排在最前面
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+        if shuffle {
+            let mut order: Vec<usize> =
+                (0..self.items.len()).filter(|&i| Some(i) != self.current).collect();
+            fastrand::shuffle(&mut order);
+            if let Some(current) = self.current {
+                order.insert(0, current);
+            }
+            self.order = order;
+        } else {
+            self.order = (0..self.items.len()).collect();
+        }
+        self.resume_position = None;
+        self.preload_dirty = true;
+    }
+
+    pub fn is_shuffled(&self) -> bool {
+        self.shuffle
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+        self.preload_dirty = true;
+    }
+
+    pub fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    // 播放列表中的第 index 项
+    pub fn play(&mut self, player: &mut Player, index: usize) -> Result<(), PlayerError> {
+        self.play_from(player, index, None)
+    }
+
+    // 从 start 处开始播放第 index 项
+    pub fn play_from(&mut self, player: &mut Player, index: usize, start: Option<Duration>) -> Result<(), PlayerError> {
+        let Some(item) = self.items.get(index) else {
+            println!("播放列表没有第 {} 项", index);
+            return Ok(());
+        };
+        println!("播放列表第 {} 项: {:?}", index, item.path);
+        self.current = Some(index);
+        self.resume_position = None;
+        self.preloaded = None;
+        player.load_from(item.path.clone(), start)?;
+        self.update_preload(player);
+        Ok(())
+    }
+
+    // 切到下一项，重复当前项时也会切走；没有下一项时返回 false
+    pub fn next(&mut self, player: &mut Player) -> Result<bool, PlayerError> {
+        match self.following(self.repeat != RepeatMode::Off) {
+            Some(index) => self.play(player, index).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    // 切到上一项，没有上一项时返回 false
+    pub fn previous(&mut self, player: &mut Player) -> Result<bool, PlayerError> {
+        match self.preceding(self.repeat != RepeatMode::Off) {
+            Some(index) => self.play(player, index).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    // 处理 Player 发出的事件：记录无缝切换，播放结束时打开下一项，并按需更新预加载
+    pub fn handle_event(&mut self, player: &mut Player, event: &PlayerEvent) -> Result<(), PlayerError> {
+        match event {
+            PlayerEvent::PreloadedStarted { path } => {
+                println!("播放列表切换到预加载的项: {:?}", path);
+                self.current = self.preloaded.take();
+                self.resume_position = None;
+                self.preload_dirty = true;
+            }
+            // 没能无缝切换，比如预加载失败或者两个文件的流组成不同
+            PlayerEvent::EndOfStream => {
+                if let Some(index) = self.automatic_next() {
+                    return self.play(player, index);
+                }
+                println!("播放列表播放完毕");
+            }
+            _ => {}
+        }
+        if self.preload_dirty {
+            self.update_preload(player);
+        }
+        Ok(())
+    }
+
+    // 当前项播完后自动播放的项
+    fn automatic_next(&self) -> Option<usize> {
+        match self.repeat {
+            RepeatMode::One => self.current.or_else(|| self.following(false)),
+            RepeatMode::All => self.following(true),
+            RepeatMode::Off => self.following(false),
+        }
+    }
+
+    // 让 Player 预加载接下来要自动播放的项
+    fn update_preload(&mut self, player: &mut Player) {
+        self.preload_dirty = false;
+        let next = self.automatic_next();
+        if next == self.preloaded {
+            return;
+        }
+        self.preloaded = None;
+        match next {
+            Some(index) => match player.preload(self.items[index].path.clone()) {
+                Ok(()) => self.preloaded = Some(index),
+                Err(e) => {
+                    println!("预加载播放列表第 {} 项失败: {}", index, e);
+                    player.cancel_preload();
+                }
+            },
+            None => player.cancel_preload(),
+        }
+    }
+
+    fn current_position(&self) -> Option<usize> {
+        self.current.and_then(|current| self.order.iter().position(|&i| i == current))
+    }
+
+    // 播放顺序中排在当前项后面的项，wrap 为 true 时到末尾后回到开头
+    fn following(&self, wrap: bool) -> Option<usize> {
+        let position = match self.current_position() {
+            Some(position) => position + 1,
+            None => self.resume_position.unwrap_or(0),
+        };
+        match self.order.get(position) {
+            Some(&index) => Some(index),
+            None if wrap => self.order.first().copied(),
+            None => None,
+        }
+    }
+
+    // 播放顺序中排在当前项前面的项，wrap 为 true 时到开头后回到末尾
+    fn preceding(&self, wrap: bool) -> Option<usize> {
+        let position = match self.current_position() {
+            Some(position) => position.checked_sub(1),
+            None => self.resume_position.and_then(|position| position.checked_sub(1)),
+        };
+        match position {
+            Some(position) => self.order.get(position).copied(),
+            None if wrap => self.order.last().copied(),
+            None => None,
+        }
+    }
+}
+
+// 解析 M3U/M3U8：#EXTINF 给出下一项的标题，其它 # 开头的行忽略
+fn parse_m3u(content: &str, base_dir: &Path) -> Vec<PlaylistItem> {
+    let mut items = Vec::new();
+    let mut title = None;
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:时长,标题
+            title = info
+                .split_once(',')
+                .map(|(_, title)| title.trim().to_string())
+                .filter(|title| !title.is_empty());
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        items.push(PlaylistItem { path: resolve_entry(line, base_dir), title: title.take() });
+    }
+    items
+}
+
+// 解析 PLS：FileN= 给出路径，TitleN= 给出标题，按 N 排序
+fn parse_pls(content: &str, base_dir: &Path) -> Vec<PlaylistItem> {
+    let mut entries: BTreeMap<u32, (Option<PathBuf>, Option<String>)> = BTreeMap::new();
+    for line in content.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+        if let Some(number) = key.strip_prefix("file").and_then(|number| number.parse().ok()) {
+            entries.entry(number).or_default().0 = Some(resolve_entry(value, base_dir));
+        } else if let Some(number) = key.strip_prefix("title").and_then(|number| number.parse().ok()) {
+            entries.entry(number).or_default().1 = Some(value.to_string()).filter(|title| !title.is_empty());
+        }
+    }
+    entries
+        .into_values()
+        .filter_map(|(path, title)| path.map(|path| PlaylistItem { path, title }))
+        .collect()
+}
+
+// Windows 的盘符 (C:\) 也能解析成 URL，只把多于一个字符的 scheme 当作 URL
+fn is_url(entry: &str) -> bool {
+    url::Url::parse(entry).is_ok_and(|url| url.scheme().len() > 1)
+}
+
+// URL 原样保留，file:// 转成本地路径，相对路径按播放列表所在目录解析
+fn resolve_entry(entry: &str, base_dir: &Path) -> PathBuf {
+    if is_url(entry) {
+        let file_path = url::Url::parse(entry)
+            .ok()
+            .filter(|url| url.scheme() == "file")
+            .and_then(|url| url.to_file_path().ok());
+        return file_path.unwrap_or_else(|| PathBuf::from(entry));
+    }
+    let path = PathBuf::from(entry);
+    if path.is_absolute() {
+        path
+    } else {
+        base_dir.join(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(names: &[&str]) -> Playlist {
+        let mut playlist = Playlist::new();
+        for name in names {
+            playlist.append(PathBuf::from(name));
+        }
+        playlist
+    }
+
+    fn name(playlist: &Playlist, index: Option<usize>) -> Option<&str> {
+        index.map(|index| playlist.items[index].path.to_str().unwrap())
+    }
+
+    #[test]
+    fn removing_current_item_continues_with_following_item() {
+        let mut playlist = playlist(&["a", "b", "c", "d"]);
+        playlist.current = Some(1);
+
+        assert_eq!(playlist.remove(1).unwrap().path, PathBuf::from("b"));
+        assert_eq!(playlist.current_index(), None);
+        assert_eq!(name(&playlist, playlist.automatic_next()), Some("c"));
+        assert_eq!(name(&playlist, playlist.preceding(false)), Some("a"));
+
+        // 再移除前面的项，接着播放的位置跟着前移
+        playlist.remove(0);
+        assert_eq!(name(&playlist, playlist.automatic_next()), Some("c"));
+        assert_eq!(playlist.preceding(false), None);
+    }
+
+    #[test]
+    fn removing_other_items_keeps_current_item() {
+        let mut playlist = playlist(&["a", "b", "c", "d"]);
+        playlist.current = Some(2);
+        playlist.preloaded = Some(3);
+
+        playlist.remove(0);
+        assert_eq!(name(&playlist, playlist.current_index()), Some("c"));
+        assert_eq!(name(&playlist, playlist.preloaded), Some("d"));
+
+        playlist.remove(2);
+        assert_eq!(name(&playlist, playlist.current_index()), Some("c"));
+        assert_eq!(playlist.preloaded, None);
+        assert_eq!(playlist.automatic_next(), None);
+        assert!(playlist.remove(5).is_none());
+    }
+
+    #[test]
+    fn moving_current_item_follows_it() {
+        let mut playlist = playlist(&["a", "b", "c", "d"]);
+        playlist.current = Some(0);
+
+        assert!(playlist.move_item(0, 2));
+        assert_eq!(name(&playlist, playlist.current_index()), Some("a"));
+        assert_eq!(playlist.current_index(), Some(2));
+        assert_eq!(name(&playlist, playlist.following(false)), Some("d"));
+        assert_eq!(name(&playlist, playlist.preceding(false)), Some("c"));
+
+        assert!(playlist.move_item(3, 0));
+        assert_eq!(playlist.current_index(), Some(3));
+        assert_eq!(playlist.following(false), None);
+        assert!(!playlist.move_item(0, 4));
+    }
+
+    #[test]
+    fn shuffle_with_repeat_plays_every_item_once_per_round() {
+        let mut playlist = playlist(&["a", "b", "c", "d", "e"]);
+        playlist.current = Some(2);
+        playlist.set_shuffle(true);
+        playlist.set_repeat(RepeatMode::All);
+
+        // 正在播放的项排在最前面
+        assert_eq!(playlist.order[0], 2);
+        let mut played = vec![2];
+        for _ in 0..4 {
+            let next = playlist.automatic_next().unwrap();
+            played.push(next);
+            playlist.current = Some(next);
+        }
+        played.sort_unstable();
+        assert_eq!(played, [0, 1, 2, 3, 4]);
+        // 一轮结束后从头开始
+        assert_eq!(playlist.automatic_next(), Some(2));
+
+        playlist.set_repeat(RepeatMode::One);
+        assert_eq!(playlist.automatic_next(), playlist.current_index());
+
+        playlist.set_repeat(RepeatMode::Off);
+        assert_eq!(playlist.automatic_next(), None);
+    }
+
+    #[test]
+    fn appending_while_shuffled_inserts_into_unplayed_part() {
+        let mut playlist = playlist(&["a", "b", "c"]);
+        playlist.current = Some(1);
+        playlist.set_shuffle(true);
+        playlist.append(PathBuf::from("d"));
+
+        let position = playlist.order.iter().position(|&i| i == 3).unwrap();
+        assert!(position > playlist.current_position().unwrap());
+        assert_eq!(playlist.order.len(), 4);
+    }
+
+    #[test]
+    fn m3u_extinf_gives_title_of_next_entry() {
+        let content = "#EXTM3U\n\
+                       #EXTINF:123,Artist - First\n\
+                       first.mp3\n\
+                       \n\
+                       #EXTINF:-1,  \n\
+                       http://example.com/live.m3u8\n\
+                       # 注释\n\
+                       sub/third.flac\n";
+        let base_dir = Path::new("music");
+        let items = parse_m3u(content, base_dir);
+
+        assert_eq!(
+            items,
+            [
+                PlaylistItem { path: base_dir.join("first.mp3"), title: Some("Artist - First".into()) },
+                PlaylistItem { path: PathBuf::from("http://example.com/live.m3u8"), title: None },
+                PlaylistItem { path: base_dir.join("sub/third.flac"), title: None },
+            ]
+        );
+    }
+
+    #[test]
+    fn pls_entries_are_ordered_by_number() {
+        let local = std::env::temp_dir().join("local.ogg");
+        let file_url = url::Url::from_file_path(&local).unwrap();
+        let content = format!(
+            "[playlist]\n\
+             File10=https://example.com/radio\n\
+             Title2=Local\n\
+             File2={}\n\
+             file1=relative.mp3\n\
+             Title1=Relative\n\
+             Title3=Missing\n\
+             NumberOfEntries=3\n\
+             Version=2\n",
+            file_url
+        );
+        let base_dir = Path::new("lists");
+        let items = parse_pls(&content, base_dir);
+
+        assert_eq!(
+            items,
+            [
+                PlaylistItem { path: base_dir.join("relative.mp3"), title: Some("Relative".into()) },
+                PlaylistItem { path: local, title: Some("Local".into()) },
+                PlaylistItem { path: PathBuf::from("https://example.com/radio"), title: None },
+            ]
+        );
+    }
+
+    #[test]
+    fn playlist_files_are_recognized_by_extension() {
+        assert!(Playlist::is_playlist_file(Path::new("list.M3U8")));
+        assert!(Playlist::is_playlist_file(Path::new("radio.pls")));
+        assert!(!Playlist::is_playlist_file(Path::new("movie.mp4")));
+        assert!(!Playlist::is_playlist_file(Path::new("m3u")));
+    }
+
+    #[test]
+    fn playlist_urls_are_left_to_ffmpeg() {
+        assert!(!Playlist::is_playlist_file(Path::new("https://example.com/live/index.m3u8")));
+        assert!(!Playlist::is_playlist_file(Path::new("http://example.com/radio.pls")));
+        assert!(Playlist::is_playlist_file(Path::new("C:\\music\\list.m3u")));
+    }
+
+    #[test]
+    fn local_hls_playlist_is_played_as_one_item() {
+        let dir = std::env::temp_dir();
+        let hls = dir.join(format!("player-rs-hls-{}.m3u8", std::process::id()));
+        std::fs::write(
+            &hls,
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\nsegment0.ts\n#EXTINF:6.0,\nsegment1.ts\n",
+        )
+        .unwrap();
+        let m3u = dir.join(format!("player-rs-list-{}.m3u8", std::process::id()));
+        std::fs::write(&m3u, "#EXTM3U\n#EXTINF:6,First\nfirst.mp4\nsecond.mp4\n").unwrap();
+
+        let hls_playlist = Playlist::from_file(&hls);
+        let m3u_playlist = Playlist::from_file(&m3u);
+        let _ = std::fs::remove_file(&hls);
+        let _ = std::fs::remove_file(&m3u);
+
+        assert_eq!(hls_playlist.unwrap().items(), [PlaylistItem::from(hls)]);
+        let m3u_playlist = m3u_playlist.unwrap();
+        assert_eq!(m3u_playlist.len(), 2);
+        assert_eq!(m3u_playlist.items()[0].path, dir.join("first.mp4"));
+    }
+}
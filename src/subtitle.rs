@@ -0,0 +1,375 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use std::path::Path;
+use std::sync::Arc;
+
+use super::error::PlayerError;
+
+// 同一时刻最多保留的已过期字幕时长，超过后从队列里删掉
+const EXPIRED_CUE_RETENTION: f64 = 1.0;
+
+// 一条字幕，时间为媒体时间秒数
+#[derive(Debug, PartialEq)]
+pub struct SubtitleCue {
+    pub start: f64,
+    // None 表示一直显示到下一条字幕出现，图形字幕常见
+    pub end: Option<f64>,
+    pub content: SubtitleContent,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubtitleContent {
+    // 去掉 ASS 样式标签后的文字，多行用 \n 分隔
+    Text(String),
+    // 图形字幕，坐标相对于 canvas 给出的画面大小
+    Bitmap { canvas: (u32, u32), rects: Vec<SubtitleBitmap> },
+}
+
+// 图形字幕的一块区域，像素为 RGBA 顺序，每行 width * 4 字节
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubtitleBitmap {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+// 解码一路字幕流，内嵌字幕和外挂字幕文件共用
+pub(crate) struct SubtitleDecoder {
+    decoder: ffmpeg::decoder::Subtitle,
+    time_base: f64,
+    // 图形字幕的参考画面大小，流里没有写时用视频大小
+    canvas: (u32, u32),
+}
+
+impl SubtitleDecoder {
+    pub(crate) fn open(
+        stream: &ffmpeg::format::stream::Stream,
+        video_size: Option<(u32, u32)>,
+    ) -> Result<Self, PlayerError> {
+        let decoder = ffmpeg::codec::Context::from_parameters(stream.parameters())
+            .and_then(|decoder_context| decoder_context.decoder().subtitle())
+            .map_err(|e| PlayerError::unsupported_codec(stream, e))?;
+        // 图形字幕流的编码参数里一般会写画面大小
+        let (width, height) = unsafe {
+            let parameters = &*stream.parameters().as_ptr();
+            (parameters.width, parameters.height)
+        };
+        let canvas = if width > 0 && height > 0 {
+            (width as u32, height as u32)
+        } else {
+            video_size.unwrap_or((720, 576))
+        };
+        Ok(Self { decoder, time_base: f64::from(stream.time_base()), canvas })
+    }
+
+    // 解码一个数据包。返回的字幕内容为空时表示清屏，之前一直显示的字幕到此结束
+    pub(crate) fn decode(
+        &mut self,
+        packet: &ffmpeg::codec::packet::packet::Packet,
+    ) -> Result<Option<(f64, Option<f64>, Option<SubtitleContent>)>, ffmpeg::Error> {
+        let mut subtitle = ffmpeg::codec::subtitle::Subtitle::new();
+        if !self.decoder.decode(packet, &mut subtitle)? {
+            return Ok(None);
+        }
+
+        // AVSubtitle 的 pts 以微秒计，显示时间是相对它的毫秒数
+        let base = subtitle
+            .pts()
+            .map(|pts| pts as f64 / ffmpeg::ffi::AV_TIME_BASE as f64)
+            .or_else(|| packet.pts().map(|pts| pts as f64 * self.time_base));
+        let decoded = base.map(|base| {
+            let start = base + subtitle.start() as f64 / 1000.0;
+            let end = if subtitle.end() > subtitle.start() && subtitle.end() != u32::MAX {
+                Some(base + subtitle.end() as f64 / 1000.0)
+            } else if packet.duration() > 0 {
+                packet.pts().map(|pts| (pts + packet.duration()) as f64 * self.time_base)
+            } else {
+                None
+            };
+            (start, end, self.content(&subtitle))
+        });
+
+        unsafe {
+            ffmpeg::ffi::avsubtitle_free(subtitle.as_mut_ptr());
+        }
+        Ok(decoded)
+    }
+
+    // 跳转后丢掉解码器里缓存的数据
+    pub(crate) fn flush(&mut self) {
+        self.decoder.flush();
+    }
+
+    fn content(&self, subtitle: &ffmpeg::codec::subtitle::Subtitle) -> Option<SubtitleContent> {
+        let mut lines = Vec::new();
+        let mut bitmaps = Vec::new();
+        for rect in subtitle.rects() {
+            match rect {
+                ffmpeg::codec::subtitle::Rect::Text(text) => lines.push(text.get().trim_end().to_string()),
+                ffmpeg::codec::subtitle::Rect::Ass(ass) => lines.push(ass_dialogue_text(ass.get())),
+                ffmpeg::codec::subtitle::Rect::Bitmap(bitmap) => {
+                    if let Some(bitmap) = palette_bitmap_to_rgba(&bitmap) {
+                        bitmaps.push(bitmap);
+                    }
+                }
+                ffmpeg::codec::subtitle::Rect::None(_) => {}
+            }
+        }
+        if !bitmaps.is_empty() {
+            Some(SubtitleContent::Bitmap { canvas: self.canvas, rects: bitmaps })
+        } else {
+            let text = lines.join("\n");
+            (!text.trim().is_empty()).then_some(SubtitleContent::Text(text))
+        }
+    }
+}
+
+// 解码后的 ASS 事件格式为 ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,Effect,Text，
+// 只取最后的文字，去掉 {} 中的样式标签
+fn ass_dialogue_text(dialogue: &str) -> String {
+    let text = dialogue.splitn(9, ',').nth(8).unwrap_or(dialogue);
+    let mut plain = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '{' => in_tag = true,
+            '}' if in_tag => in_tag = false,
+            c if !in_tag => plain.push(c),
+            _ => {}
+        }
+    }
+    plain.replace("\\N", "\n").replace("\\n", "\n").replace("\\h", " ").trim_end().to_string()
+}
+
+// 图形字幕是调色板图像，转换成 RGBA
+fn palette_bitmap_to_rgba(bitmap: &ffmpeg::codec::subtitle::Bitmap) -> Option<SubtitleBitmap> {
+    let (width, height) = (bitmap.width(), bitmap.height());
+    if width == 0 || height == 0 {
+        return None;
+    }
+    // ffmpeg-next 在 FFmpeg 5 之后没有提供像素访问，直接读 AVSubtitleRect
+    let rgba = unsafe {
+        let rect = &*bitmap.as_ptr();
+        if rect.data[0].is_null() || rect.data[1].is_null() {
+            return None;
+        }
+        let palette = std::slice::from_raw_parts(rect.data[1] as *const u32, rect.nb_colors.max(0) as usize);
+        let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height as usize {
+            let row = std::slice::from_raw_parts(rect.data[0].add(y * rect.linesize[0] as usize), width as usize);
+            for &index in row {
+                // 调色板按本机字节序存放 0xAARRGGBB
+                let argb = palette.get(index as usize).copied().unwrap_or(0);
+                rgba.extend_from_slice(&[(argb >> 16) as u8, (argb >> 8) as u8, argb as u8, (argb >> 24) as u8]);
+            }
+        }
+        rgba
+    };
+    Some(SubtitleBitmap { x: bitmap.x() as i32, y: bitmap.y() as i32, width, height, rgba })
+}
+
+// 读取外挂字幕文件的全部字幕，格式由 FFmpeg 识别，支持 SRT、ASS、WebVTT 等
+pub(crate) fn read_subtitle_file(path: &Path) -> Result<SubtitleTrack, PlayerError> {
+    let mut input_context = ffmpeg::format::input(&path)
+        .map_err(|source| PlayerError::Open { path: path.to_path_buf(), source })?;
+    let stream = input_context
+        .streams()
+        .best(ffmpeg::media::Type::Subtitle)
+        .ok_or_else(|| PlayerError::NoSubtitleStream { path: path.to_path_buf() })?;
+    let stream_index = stream.index();
+    let mut decoder = SubtitleDecoder::open(&stream, None)?;
+
+    let mut track = SubtitleTrack { external: true, ..SubtitleTrack::default() };
+    for (stream, packet) in input_context.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(Some((start, end, content))) => track.push(start, end, content),
+            Ok(None) => {}
+            Err(e) => println!("外挂字幕解码失败: {}", e),
+        }
+    }
+    println!("读取外挂字幕 {:?}: {} 条", path, track.cues.len());
+    Ok(track)
+}
+
+struct QueuedCue {
+    // 实际结束时间，没有结束时间的字幕在下一条出现时结束
+    end: f64,
+    cue: Arc<SubtitleCue>,
+}
+
+// 当前字幕轨的字幕。内嵌字幕由解复用线程边读边写入，外挂字幕一次性写入；
+// 界面线程按主时钟取出正在显示的字幕
+#[derive(Default)]
+pub(crate) struct SubtitleTrack {
+    // 按开始时间排序
+    cues: Vec<QueuedCue>,
+    // 字幕延迟秒数，正数表示字幕晚出现
+    delay: f64,
+    // 外挂字幕跳转时不清空
+    external: bool,
+}
+
+impl SubtitleTrack {
+    // 写入一条字幕，content 为 None 时只结束之前一直显示的字幕
+    pub(crate) fn push(&mut self, start: f64, end: Option<f64>, content: Option<SubtitleContent>) {
+        for queued in &mut self.cues {
+            if queued.cue.end.is_none() && queued.cue.start < start && queued.end > start {
+                queued.end = start;
+            }
+        }
+        let Some(content) = content else {
+            return;
+        };
+        // 循环播放时同一段字幕会再解码一次
+        if self.cues.iter().any(|queued| queued.cue.start == start && queued.cue.content == content) {
+            return;
+        }
+        let cue = Arc::new(SubtitleCue { start, end, content });
+        let position = self.cues.partition_point(|queued| queued.cue.start <= start);
+        self.cues.insert(position, QueuedCue { end: end.unwrap_or(f64::INFINITY), cue });
+    }
+
+    // 换成外挂字幕文件里的字幕，延迟设置保留
+    pub(crate) fn replace_with_external(&mut self, track: SubtitleTrack) {
+        self.cues = track.cues;
+        self.external = true;
+    }
+
+    // 换成内嵌字幕流或关闭字幕
+    pub(crate) fn reset_embedded(&mut self) {
+        self.cues.clear();
+        self.external = false;
+    }
+
+    pub(crate) fn is_external(&self) -> bool {
+        self.external
+    }
+
+    // 跳转后内嵌字幕从新位置重新解码
+    pub(crate) fn clear_embedded(&mut self) {
+        if !self.external {
+            self.cues.clear();
+        }
+    }
+
+    pub(crate) fn set_delay(&mut self, delay: f64) {
+        self.delay = delay;
+    }
+
+    pub(crate) fn delay(&self) -> f64 {
+        self.delay
+    }
+
+    // time 时刻应该显示的字幕，内嵌字幕顺便清掉早已结束的
+    pub(crate) fn active(&mut self, time: f64) -> Vec<Arc<SubtitleCue>> {
+        let time = time - self.delay;
+        if !self.external {
+            self.cues.retain(|queued| queued.end >= time - EXPIRED_CUE_RETENTION);
+        }
+        self.cues
+            .iter()
+            .filter(|queued| queued.cue.start <= time && time < queued.end)
+            .map(|queued| queued.cue.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Option<SubtitleContent> {
+        Some(SubtitleContent::Text(text.to_string()))
+    }
+
+    fn active_texts(track: &mut SubtitleTrack, time: f64) -> Vec<String> {
+        track
+            .active(time)
+            .iter()
+            .map(|cue| match &cue.content {
+                SubtitleContent::Text(text) => text.clone(),
+                SubtitleContent::Bitmap { .. } => "bitmap".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ass_dialogue_text_strips_fields_and_tags() {
+        assert_eq!(ass_dialogue_text("0,0,Default,,0,0,0,,{\\i1}Hello{\\i0}\\NWorld\\h!"), "Hello\nWorld !");
+        // 文字里的逗号要保留
+        assert_eq!(ass_dialogue_text("3,0,Default,Name,0,0,0,,One, two, three  "), "One, two, three");
+        assert_eq!(ass_dialogue_text("1,0,Default,,0,0,0,,line\\nbreak"), "line\nbreak");
+        // 不是 ASS 事件格式时整段当作文字
+        assert_eq!(ass_dialogue_text("plain {\\b1}text"), "plain text");
+    }
+
+    #[test]
+    fn open_ended_cue_ends_at_next_cue() {
+        let mut track = SubtitleTrack::default();
+        track.push(1.0, None, text("first"));
+        track.push(3.0, Some(4.0), text("second"));
+
+        assert_eq!(active_texts(&mut track, 2.0), ["first"]);
+        assert_eq!(active_texts(&mut track, 3.5), ["second"]);
+
+        // 没有内容的字幕只结束之前一直显示的字幕
+        track.push(5.0, None, text("third"));
+        track.push(6.0, None, None);
+        assert_eq!(active_texts(&mut track, 5.5), ["third"]);
+        assert!(active_texts(&mut track, 6.5).is_empty());
+    }
+
+    #[test]
+    fn same_cue_decoded_again_is_not_duplicated() {
+        let mut track = SubtitleTrack::default();
+        track.push(1.0, Some(2.0), text("loop"));
+        track.push(1.0, Some(2.0), text("loop"));
+        track.push(1.0, Some(2.0), text("other"));
+
+        assert_eq!(active_texts(&mut track, 1.5), ["loop", "other"]);
+    }
+
+    #[test]
+    fn delay_shifts_cues() {
+        let mut track = SubtitleTrack::default();
+        track.set_delay(0.5);
+        track.push(1.0, Some(2.0), text("late"));
+
+        assert!(active_texts(&mut track, 1.2).is_empty());
+        assert_eq!(active_texts(&mut track, 1.6), ["late"]);
+        assert_eq!(active_texts(&mut track, 2.4), ["late"]);
+        assert!(active_texts(&mut track, 2.6).is_empty());
+    }
+
+    #[test]
+    fn expired_embedded_cues_are_dropped_after_retention() {
+        let mut track = SubtitleTrack::default();
+        track.push(1.0, Some(2.0), text("embedded"));
+
+        assert!(active_texts(&mut track, 2.5).is_empty());
+        assert_eq!(track.cues.len(), 1);
+        assert!(active_texts(&mut track, 2.0 + EXPIRED_CUE_RETENTION + 0.5).is_empty());
+        assert!(track.cues.is_empty());
+    }
+
+    #[test]
+    fn external_cues_survive_expiry_and_seeking() {
+        let mut external = SubtitleTrack::default();
+        external.push(1.0, Some(2.0), text("external"));
+        let mut track = SubtitleTrack::default();
+        track.replace_with_external(external);
+
+        assert!(active_texts(&mut track, 10.0).is_empty());
+        track.clear_embedded();
+        assert_eq!(active_texts(&mut track, 1.5), ["external"]);
+
+        track.reset_embedded();
+        assert!(!track.is_external());
+        assert!(active_texts(&mut track, 1.5).is_empty());
+    }
+}
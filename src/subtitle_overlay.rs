@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
+use sdl2::ttf::{Font, Sdl2TtfContext};
+use sdl2::video::{Window, WindowContext};
+
+use crate::subtitle::{SubtitleContent, SubtitleCue};
+
+// 字体按这个大小渲染，绘制时再按画面大小缩放
+const FONT_POINT_SIZE: u16 = 48;
+// 一行文字占画面高度的比例
+const LINE_HEIGHT_RATIO: f32 = 0.055;
+// 文字底边离画面底边的距离占画面高度的比例
+const BOTTOM_MARGIN_RATIO: f32 = 0.05;
+// 没有指定字体时依次尝试的系统字体，能显示中文的排在前面
+const FALLBACK_FONTS: &[&str] = &[
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/System/Library/Fonts/PingFang.ttc",
+    "/System/Library/Fonts/Helvetica.ttc",
+    "C:\\Windows\\Fonts\\msyh.ttc",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+// 生成好的一块字幕纹理
+enum Layer<'a> {
+    // 文字在画面底部居中，宽高为纹理像素大小
+    Text { texture: Texture<'a>, width: u32, height: u32 },
+    // 图形字幕按参考画面大小换算位置
+    Bitmap { texture: Texture<'a>, canvas: (u32, u32), rect: Rect },
+}
+
+// 字幕层，画面画好之后叠加在上面；字幕变化时才重新生成纹理
+pub struct SubtitleOverlay<'ttf, 'a> {
+    texture_creator: &'a TextureCreator<WindowContext>,
+    font: Option<Font<'ttf, 'static>>,
+    cues: Vec<Arc<SubtitleCue>>,
+    layers: Vec<Layer<'a>>,
+}
+
+impl<'ttf, 'a> SubtitleOverlay<'ttf, 'a> {
+    pub fn new(
+        ttf_context: &'ttf Sdl2TtfContext,
+        font_path: Option<&Path>,
+        texture_creator: &'a TextureCreator<WindowContext>,
+    ) -> Self {
+        let candidates: Vec<PathBuf> = match font_path {
+            Some(font_path) => vec![font_path.to_path_buf()],
+            None => FALLBACK_FONTS.iter().map(PathBuf::from).collect(),
+        };
+        let font = candidates.iter().find_map(|path| match ttf_context.load_font(path, FONT_POINT_SIZE) {
+            Ok(font) => {
+                println!("字幕字体: {:?}", path);
+                Some(font)
+            }
+            Err(_) => None,
+        });
+        if font.is_none() {
+            println!("没有可用的字幕字体, 不显示文字字幕");
+        }
+        Self { texture_creator, font, cues: Vec::new(), layers: Vec::new() }
+    }
+
+    // 换成新的字幕，和上次相同时沿用已经生成的纹理。返回字幕是否有变化
+    pub fn update(&mut self, cues: Vec<Arc<SubtitleCue>>) -> bool {
+        if cues.len() == self.cues.len() && cues.iter().zip(&self.cues).all(|(new, old)| Arc::ptr_eq(new, old)) {
+            return false;
+        }
+        self.layers.clear();
+        for cue in &cues {
+            match &cue.content {
+                SubtitleContent::Text(text) => match self.render_text(text) {
+                    Ok(layer) => self.layers.push(layer),
+                    Err(e) => println!("渲染字幕文字失败: {}", e),
+                },
+                SubtitleContent::Bitmap { canvas, rects } => {
+                    for bitmap in rects {
+                        let texture = self
+                            .texture_creator
+                            .create_texture_static(PixelFormatEnum::RGBA32, bitmap.width, bitmap.height)
+                            .map_err(|e| e.to_string())
+                            .and_then(|mut texture| {
+                                texture
+                                    .update(None, &bitmap.rgba, bitmap.width as usize * 4)
+                                    .map_err(|e| e.to_string())?;
+                                texture.set_blend_mode(BlendMode::Blend);
+                                Ok(texture)
+                            });
+                        match texture {
+                            Ok(texture) => self.layers.push(Layer::Bitmap {
+                                texture,
+                                canvas: *canvas,
+                                rect: Rect::new(bitmap.x, bitmap.y, bitmap.width, bitmap.height),
+                            }),
+                            Err(e) => println!("生成图形字幕纹理失败: {}", e),
+                        }
+                    }
+                }
+            }
+        }
+        self.cues = cues;
+        true
+    }
+
+    fn render_text(&self, text: &str) -> Result<Layer<'a>, String> {
+        let font = self.font.as_ref().ok_or("没有字幕字体")?;
+        // 只在原文换行处换行
+        let surface = font
+            .render(text)
+            .blended_wrapped(Color::WHITE, u32::from(FONT_POINT_SIZE) * 60)
+            .map_err(|e| e.to_string())?;
+        let (width, height) = (surface.width(), surface.height());
+        let texture = self.texture_creator.create_texture_from_surface(&surface).map_err(|e| e.to_string())?;
+        Ok(Layer::Text { texture, width, height })
+    }
+
+    // 叠加到画面上，video_rect 为画面在窗口中的位置，可能超出窗口
+    pub fn draw(&self, canvas: &mut Canvas<Window>, video_rect: Rect) -> Result<(), String> {
+        if self.layers.is_empty() {
+            return Ok(());
+        }
+        let (window_width, window_height) = canvas.output_size()?;
+        // 文字放在画面实际可见的部分里
+        let visible = video_rect
+            .intersection(Rect::new(0, 0, window_width, window_height))
+            .unwrap_or(video_rect);
+        let line_height = self.font.as_ref().map_or(1, |font| font.height().max(1)) as f32;
+        let text_scale = visible.height() as f32 * LINE_HEIGHT_RATIO / line_height;
+        let mut bottom = visible.bottom() - (visible.height() as f32 * BOTTOM_MARGIN_RATIO) as i32;
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        // 多条文字字幕从下往上排
+        for layer in self.layers.iter().rev() {
+            match layer {
+                Layer::Text { texture, width, height } => {
+                    let scale = text_scale.min(visible.width() as f32 / *width as f32);
+                    let (width, height) = ((*width as f32 * scale) as u32, (*height as f32 * scale) as u32);
+                    let target = Rect::new(visible.center().x() - width as i32 / 2, bottom - height as i32, width, height);
+                    // 半透明底色让文字在亮的画面上也看得清
+                    let padding = (line_height * scale * 0.15) as i32;
+                    canvas.set_draw_color(Color::RGBA(0, 0, 0, 128));
+                    canvas.fill_rect(Rect::new(
+                        target.x() - padding,
+                        target.y() - padding,
+                        width + padding as u32 * 2,
+                        height + padding as u32 * 2,
+                    ))?;
+                    canvas.copy(texture, None, Some(target))?;
+                    bottom = target.y() - padding * 2;
+                }
+                Layer::Bitmap { texture, canvas: (canvas_width, canvas_height), rect } => {
+                    let scale_x = video_rect.width() as f32 / *canvas_width as f32;
+                    let scale_y = video_rect.height() as f32 / *canvas_height as f32;
+                    let target = Rect::new(
+                        video_rect.x() + (rect.x() as f32 * scale_x) as i32,
+                        video_rect.y() + (rect.y() as f32 * scale_y) as i32,
+                        (rect.width() as f32 * scale_x).max(1.0) as u32,
+                        (rect.height() as f32 * scale_y).max(1.0) as u32,
+                    );
+                    canvas.copy(texture, None, Some(target))?;
+                }
+            }
+        }
+        canvas.set_blend_mode(BlendMode::None);
+        Ok(())
+    }
+}
@@ -1,77 +1,344 @@
 extern crate ffmpeg_next as ffmpeg;
 
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use futures::{future::OptionFuture, FutureExt};
 
-use super::player::ControlCommand;
+use super::clock::{MasterClock, SyncMode};
+use super::error::{ErrorCallback, PlayerError};
+use super::player::{queue_fill_percent, ControlCommand, DecoderTransition, PacketMessage, PreparedDecoder};
+
+// 与主时钟相差超过该值时认为时间戳不连续，不再做同步
+const NOSYNC_THRESHOLD: f64 = 10.0;
+// 单次等待的上限，等待期间主时钟可能被跳转重置
+const MAX_FRAME_WAIT: f64 = 0.1;
+
+// 解码跟不上时的丢帧策略，只在以音频或外部时钟为准时生效
+#[derive(Clone, Copy, Debug)]
+pub struct FrameDropPolicy {
+    pub enabled: bool,
+    // 落后主时钟超过该时长的帧不再显示
+    pub late_threshold: Duration,
+    // 连续丢弃这么多帧后让解码器跳过非参考帧和环路滤波，None 表示不启用
+    pub decoder_skip_after: Option<u32>,
+}
+
+impl Default for FrameDropPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            late_threshold: Duration::from_millis(100),
+            decoder_skip_after: Some(5),
+        }
+    }
+}
+
+// 一帧相对主时钟的处理方式
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FrameTiming {
+    Show,
+    // 落后主时钟超过阈值
+    Late,
+    // 还没到显示时间，等待这么多秒后再比较
+    Wait(f64),
+}
+
+impl FrameDropPolicy {
+    fn frame_timing(&self, frame_time: f64, master_time: f64, speed: f64) -> FrameTiming {
+        let diff = frame_time - master_time;
+        // 相差太多多半是时钟刚重置或时间戳跳变，直接显示
+        if diff.abs() > NOSYNC_THRESHOLD {
+            return FrameTiming::Show;
+        }
+        if diff < 0.0001 {
+            return if -diff > self.late_threshold.as_secs_f64() { FrameTiming::Late } else { FrameTiming::Show };
+        }
+        FrameTiming::Wait((diff / speed).min(MAX_FRAME_WAIT))
+    }
+
+    // 以视频为准时视频本身就是主时钟，落后也不丢
+    fn should_drop(&self, late: bool, sync_mode: SyncMode) -> bool {
+        late && self.enabled && sync_mode != SyncMode::Video
+    }
+
+    // 连续丢弃这么多帧后是否让解码器跳过非参考帧
+    fn should_skip_decoding(&self, consecutive_drops: u32) -> bool {
+        self.decoder_skip_after.is_some_and(|limit| consecutive_drops >= limit)
+    }
+}
+
+// 视频线程的帧统计，可在其它线程读取
+#[derive(Default)]
+pub struct VideoStatistics {
+    frames_presented: AtomicU64,
+    frames_dropped: AtomicU64,
+    decoder_skipping: AtomicBool,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStatistics {
+    pub frames_presented: u64,
+    pub frames_dropped: u64,
+    // 解码器当前是否在跳过非参考帧
+    pub decoder_skipping: bool,
+}
+
+impl VideoStatistics {
+    pub fn snapshot(&self) -> FrameStatistics {
+        FrameStatistics {
+            frames_presented: self.frames_presented.load(Ordering::Relaxed),
+            frames_dropped: self.frames_dropped.load(Ordering::Relaxed),
+            decoder_skipping: self.decoder_skipping.load(Ordering::Relaxed),
+        }
+    }
+}
 
 pub struct VideoPlaybackThread {
     control_sender: smol::channel::Sender<ControlCommand>,
-    packet_sender: smol::channel::Sender<ffmpeg::codec::packet::packet::Packet>,
+    packet_sender: smol::channel::Sender<PacketMessage>,
+    // 保留一个接收端，跳转时由解复用线程直接清空队列
+    packet_receiver: smol::channel::Receiver<PacketMessage>,
+    // 收到结束标记并把解码器里剩余的帧都显示完后置位
+    finished: Arc<AtomicBool>,
+    // 已请求清空但解码线程还没处理，期间解出的旧帧一律不显示
+    flush_pending: Arc<AtomicBool>,
+    // 最近一次显示的帧时间，单帧后退以它为基准
+    last_frame_time: Arc<Mutex<Option<f64>>>,
+    // 帧间隔秒数，取自流的平均帧率，无缝切换到下一个文件时更新
+    frame_duration: Cell<f64>,
     receiver_thread: Option<std::thread::JoinHandle<()>>,
 }
 
 impl VideoPlaybackThread {
     pub fn start(
         stream: &ffmpeg::format::stream::Stream,
+        master_clock: Arc<MasterClock>,
+        frame_drop_policy: FrameDropPolicy,
+        statistics: Arc<VideoStatistics>,
+        filter_settings: Arc<VideoFilterSettings>,
+        error_callback: ErrorCallback,
         mut video_frame_callback: Box<dyn FnMut(&ffmpeg::util::frame::Video) + Send>,
-    ) -> Result<Self, anyhow::Error> {
+    ) -> Result<Self, PlayerError> {
         println!("视频线程启动 - 流信息: {}", stream.duration());
 
         let (control_sender, control_receiver) = smol::channel::unbounded();
 
         let (packet_sender, packet_receiver) = smol::channel::bounded(128);
 
-        let decoder_context = ffmpeg::codec::Context::from_parameters(stream.parameters())?;
-        let mut packet_decoder = decoder_context.decoder().video()?;
+        let mut packet_decoder = open_decoder(stream)?;
 
         println!("视频解码器初始化完成 - {:?}", packet_decoder.format());
 
-        let clock = StreamClock::new(stream);
+        let mut clock = StreamClock::new(stream);
+        let mut filter_stage = FilterStage::new(filter_settings, DisplayGeometry::new(stream));
+        let frame_duration = frame_duration(stream);
 
+        let finished = Arc::new(AtomicBool::new(false));
+        let thread_finished = finished.clone();
+        let flush_pending = Arc::new(AtomicBool::new(false));
+        let thread_flush_pending = flush_pending.clone();
+        let last_frame_time = Arc::new(Mutex::new(None));
+        let thread_last_frame_time = last_frame_time.clone();
+        let thread_packet_receiver = packet_receiver.clone();
         let receiver_thread =
             std::thread::Builder::new().name("video playback thread".into()).spawn(move || {
                 smol::block_on(async move {
+                    let playing = Cell::new(true);
+                    // 暂停时还需要显示的帧数
+                    let steps = Cell::new(0u32);
+
                     let packet_receiver_impl = async {
+                        // 精确跳转时，早于该时间戳的帧解码后直接丢弃
+                        let mut discard_before_pts: Option<i64> = None;
+                        let mut consecutive_drops = 0u32;
                         loop {
-                            let Ok(packet) = packet_receiver.recv().await else { 
+                            let Ok(message) = thread_packet_receiver.recv().await else { 
                                 // println!("视频包接收结束");
                                 break 
                             };
 
+                            // 切换到下一个文件或循环跳回前先把当前解码器里的帧都显示完
+                            let (packet, transition) = match message {
+                                PacketMessage::Packet(packet) => (Some(packet), None),
+                                PacketMessage::EndOfStream => (None, None),
+                                PacketMessage::Switch { decoder: PreparedDecoder::Video { decoder, geometry }, time_base } => {
+                                    (None, Some(DecoderTransition::Switch { decoder: (decoder, geometry), time_base }))
+                                }
+                                PacketMessage::Restart { target } => (None, Some(DecoderTransition::Restart { target })),
+                                PacketMessage::Switch { .. } => {
+                                    println!("视频线程收到非视频解码器, 忽略");
+                                    continue;
+                                }
+                                PacketMessage::SwitchTrack { .. } => {
+                                    println!("视频线程不支持切换轨道, 忽略");
+                                    continue;
+                                }
+                                PacketMessage::Flush { target } => {
+                                    println!("视频解码器清空, 精确跳转目标: {:?}", target);
+                                    packet_decoder.flush();
+                                    filter_stage.reset();
+                                    master_clock.video().reset();
+                                    thread_finished.store(false, Ordering::Relaxed);
+                                    thread_flush_pending.store(false, Ordering::Relaxed);
+                                    discard_before_pts = target.map(|target| clock.seconds_to_pts(target));
+                                    continue;
+                                }
+                            };
+
                             smol::future::yield_now().await;
 
-                            if let Err(e) = packet_decoder.send_packet(&packet) {
+                            // 没有数据包表示读到了文件末尾，让解码器吐出缓存的帧
+                            let sent = match &packet {
+                                Some(packet) => packet_decoder.send_packet(packet),
+                                None => packet_decoder.send_eof(),
+                            };
+                            if let Err(e) = sent {
                                 println!("发送视频包到解码器失败: {}", e);
-                                continue;
+                                error_callback(PlayerError::Decode {
+                                    media_type: ffmpeg::media::Type::Video,
+                                    source: e,
+                                });
+                                if packet.is_some() {
+                                    continue;
+                                }
                             }
 
                             let mut decoded_frame = ffmpeg::util::frame::Video::empty();
+                            let mut filter_drained = false;
 
-                            while packet_decoder.receive_frame(&mut decoded_frame).is_ok() {
-                                if let Some(delay) = clock.convert_pts_to_instant(decoded_frame.pts()) {
-                                    // 使用更精确的帧定时
-                                    let now = std::time::Instant::now();
-                                    let current = now.duration_since(clock.start_time);
-                                    if delay > current {
-                                        let sleep_duration = delay - current;
-                                        if sleep_duration > std::time::Duration::from_micros(100) {
-                                            smol::Timer::after(sleep_duration).await;
+                            loop {
+                                // 经过滤镜后一帧解码输出可能对应零帧或多帧
+                                let frames = if packet_decoder.receive_frame(&mut decoded_frame).is_ok() {
+                                    if let (Some(discard_before), Some(pts)) = (discard_before_pts, decoded_frame.timestamp()) {
+                                        if pts < discard_before {
+                                            continue;
                                         }
+                                        discard_before_pts = None;
                                     }
+                                    let decoded_frame = std::mem::replace(&mut decoded_frame, ffmpeg::util::frame::Video::empty());
+                                    filter_stage.process(decoded_frame, &clock, &error_callback)
+                                } else if packet.is_none() && !filter_drained {
+                                    // 解码器已经吐完，再取出滤镜里缓存的帧
+                                    filter_drained = true;
+                                    filter_stage.drain()
+                                } else {
+                                    break;
+                                };
+
+                                for (decoded_frame, frame_time) in frames {
+                                    // 暂停时停在这一帧上，直到继续播放或收到单帧步进
+                                    while !playing.get()
+                                        && steps.get() == 0
+                                        && !thread_flush_pending.load(Ordering::Relaxed)
+                                    {
+                                        futures::pending!();
+                                    }
+                                    if thread_flush_pending.load(Ordering::Relaxed) {
+                                        continue;
+                                    }
+
+                                    let Some(frame_time) = frame_time else {
+                                        video_frame_callback(&decoded_frame);
+                                        if !playing.get() {
+                                            steps.set(steps.get().saturating_sub(1));
+                                        }
+                                        continue;
+                                    };
+
+                                    master_clock.start_external(frame_time);
+
+                                    // 单帧步进时不等待也不丢帧，直接显示
+                                    if !playing.get() {
+                                        println!("单帧步进显示: {:.3}", frame_time);
+                                        video_frame_callback(&decoded_frame);
+                                        statistics.frames_presented.fetch_add(1, Ordering::Relaxed);
+                                        steps.set(steps.get().saturating_sub(1));
+                                        master_clock.video().set(frame_time);
+                                        if master_clock.sync_mode() == SyncMode::External {
+                                            master_clock.external().set(frame_time);
+                                        }
+                                        *thread_last_frame_time.lock().unwrap() = Some(frame_time);
+                                        continue;
+                                    }
+
+                                    // 按主时钟决定显示、等待还是丢弃这一帧
+                                    let mut late = false;
+                                    while let Some(master_time) = master_clock.get() {
+                                        match frame_drop_policy.frame_timing(frame_time, master_time, master_clock.speed()) {
+                                            FrameTiming::Show => break,
+                                            FrameTiming::Late => {
+                                                late = true;
+                                                break;
+                                            }
+                                            FrameTiming::Wait(wait) => {
+                                                smol::Timer::after(Duration::from_secs_f64(wait)).await;
+                                            }
+                                        }
+                                    }
+
+                                    if frame_drop_policy.should_drop(late, master_clock.sync_mode()) {
+                                        println!("视频帧落后主时钟, 丢弃: {:.3}", frame_time);
+                                        statistics.frames_dropped.fetch_add(1, Ordering::Relaxed);
+                                        consecutive_drops += 1;
+                                        // 持续落后时让解码器少做一些工作
+                                        if frame_drop_policy.should_skip_decoding(consecutive_drops)
+                                            && !statistics.decoder_skipping.swap(true, Ordering::Relaxed)
+                                        {
+                                            println!("视频解码持续落后, 跳过非参考帧");
+                                            packet_decoder.skip_frame(ffmpeg::codec::discard::Discard::NonReference);
+                                            packet_decoder.skip_loop_filter(ffmpeg::codec::discard::Discard::All);
+                                        }
+                                        continue;
+                                    }
+
+                                    consecutive_drops = 0;
+                                    if !late && statistics.decoder_skipping.swap(false, Ordering::Relaxed) {
+                                        println!("视频解码已追上, 恢复完整解码");
+                                        packet_decoder.skip_frame(ffmpeg::codec::discard::Discard::Default);
+                                        packet_decoder.skip_loop_filter(ffmpeg::codec::discard::Discard::Default);
+                                    }
+
+                                    video_frame_callback(&decoded_frame);
+                                    statistics.frames_presented.fetch_add(1, Ordering::Relaxed);
+                                    master_clock.video().set(frame_time);
+                                    *thread_last_frame_time.lock().unwrap() = Some(frame_time);
                                 }
+                            }
 
-                                video_frame_callback(&decoded_frame);
+                            match transition {
+                                Some(DecoderTransition::Switch { decoder: (decoder, geometry), time_base }) => {
+                                    println!("视频切换到下一个文件");
+                                    packet_decoder = decoder;
+                                    clock = StreamClock::from_time_base(time_base);
+                                    filter_stage.set_geometry(geometry);
+                                    discard_before_pts = None;
+                                    consecutive_drops = 0;
+                                    statistics.decoder_skipping.store(false, Ordering::Relaxed);
+                                    master_clock.video().reset();
+                                }
+                                Some(DecoderTransition::Restart { target }) => {
+                                    println!("视频循环跳回, 精确定位目标: {:?}", target);
+                                    packet_decoder.flush();
+                                    discard_before_pts = target.map(|target| clock.seconds_to_pts(target));
+                                    master_clock.video().reset();
+                                }
+                                None if packet.is_none() => {
+                                    println!("视频播放到结尾");
+                                    thread_finished.store(true, Ordering::Relaxed);
+                                }
+                                None => {}
                             }
                         }
                     }
                     .fuse()
                     .shared();
 
-                    let mut playing = true;
-
                     loop {
                         let packet_receiver: OptionFuture<_> =
-                            if playing { Some(packet_receiver_impl.clone()) } else { None }.into();
+                            if playing.get() || steps.get() > 0 { Some(packet_receiver_impl.clone()) } else { None }.into();
 
                         smol::pin!(packet_receiver);
 
@@ -81,11 +348,19 @@ impl VideoPlaybackThread {
                                 match received_command {
                                     Ok(ControlCommand::Pause) => {
                                         println!("视频播放暂停");
-                                        playing = false;
+                                        playing.set(false);
                                     }
                                     Ok(ControlCommand::Play) => {
                                         println!("视频播放开始");
-                                        playing = true;
+                                        playing.set(true);
+                                        steps.set(0);
+                                    }
+                                    Ok(ControlCommand::StepForward) => {
+                                        println!("视频单帧步进");
+                                        steps.set(steps.get() + 1);
+                                    }
+                                    Ok(command) => {
+                                        println!("视频线程忽略控制命令: {:?}", command);
                                     }
                                     Err(e) => {
                                         println!("视频控制通道关闭,{}",e);
@@ -98,11 +373,20 @@ impl VideoPlaybackThread {
                 })
             })?;
 
-        Ok(Self { control_sender, packet_sender, receiver_thread: Some(receiver_thread) })
+        Ok(Self {
+            control_sender,
+            packet_sender,
+            packet_receiver,
+            finished,
+            flush_pending,
+            last_frame_time,
+            frame_duration: Cell::new(frame_duration),
+            receiver_thread: Some(receiver_thread),
+        })
     }
 
     pub async fn receive_packet(&self, packet: ffmpeg::codec::packet::packet::Packet) -> bool {
-        match self.packet_sender.send(packet).await {
+        match self.packet_sender.send(PacketMessage::Packet(packet)).await {
             Ok(_) => {
                 // println!("视频发送成功");
                 true
@@ -114,6 +398,73 @@ impl VideoPlaybackThread {
         }
     }
 
+    // 丢弃队列中尚未解码的数据包
+    pub fn discard_queued_packets(&self) {
+        let mut discarded = 0;
+        while self.packet_receiver.try_recv().is_ok() {
+            discarded += 1;
+        }
+        println!("丢弃视频队列中的数据包: {}", discarded);
+    }
+
+    // 跳转后通知解码线程清空解码器并重置时钟
+    pub async fn flush(&self, target: Option<f64>) {
+        self.discard_queued_packets();
+        // 解码线程处理清空消息前，不能再沿用上一次的结束状态
+        self.finished.store(false, Ordering::Relaxed);
+        self.flush_pending.store(true, Ordering::Relaxed);
+        if let Err(e) = self.packet_sender.send(PacketMessage::Flush { target }).await {
+            println!("发送视频清空消息失败: {}", e);
+        }
+    }
+
+    // 文件读完后通知解码线程把剩余的帧都解出来
+    pub async fn end_of_stream(&self) {
+        if let Err(e) = self.packet_sender.send(PacketMessage::EndOfStream).await {
+            println!("发送视频结束消息失败: {}", e);
+        }
+    }
+
+    // 最近一次显示的帧时间，还没有显示过帧时返回 None
+    pub fn last_frame_time(&self) -> Option<f64> {
+        *self.last_frame_time.lock().unwrap()
+    }
+
+    pub fn frame_duration(&self) -> f64 {
+        self.frame_duration.get()
+    }
+
+    // 当前文件读完后换成预先打开的下一个文件的解码器，已排队的帧照常显示
+    pub async fn switch_decoder(
+        &self,
+        decoder: ffmpeg::decoder::Video,
+        time_base: ffmpeg::Rational,
+        frame_duration: f64,
+        geometry: DisplayGeometry,
+    ) {
+        self.frame_duration.set(frame_duration);
+        let message = PacketMessage::Switch { decoder: PreparedDecoder::Video { decoder, geometry }, time_base };
+        if let Err(e) = self.packet_sender.send(message).await {
+            println!("发送视频切换消息失败: {}", e);
+        }
+    }
+
+    // 循环播放跳回起点，已排队的帧照常显示
+    pub async fn restart(&self, target: Option<f64>) {
+        if let Err(e) = self.packet_sender.send(PacketMessage::Restart { target }).await {
+            println!("发送视频循环消息失败: {}", e);
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+
+    // 队列中待解码的数据包占容量的百分比
+    pub fn buffer_percent(&self) -> u8 {
+        queue_fill_percent(&self.packet_sender)
+    }
+
     pub async fn send_control_message(&self, message: ControlCommand) {
         println!("发送控制消息: {:?}", message);
         if let Err(e) = self.control_sender.send(message).await {
@@ -132,28 +483,413 @@ impl Drop for VideoPlaybackThread {
     }
 }
 
+pub(crate) fn open_decoder(stream: &ffmpeg::format::stream::Stream) -> Result<ffmpeg::decoder::Video, PlayerError> {
+    ffmpeg::codec::Context::from_parameters(stream.parameters())
+        .and_then(|decoder_context| decoder_context.decoder().video())
+        .map_err(|e| PlayerError::unsupported_codec(stream, e))
+}
+
+// 帧间隔秒数，平均帧率未知时按 25 帧计算
+pub(crate) fn frame_duration(stream: &ffmpeg::format::stream::Stream) -> f64 {
+    let frame_rate = stream.avg_frame_rate();
+    if frame_rate.numerator() > 0 && frame_rate.denominator() > 0 {
+        f64::from(frame_rate.invert())
+    } else {
+        1.0 / 25.0
+    }
+}
+
+// 视频流的显示参数，滤镜阶段据此旋转画面、补上帧里缺少的像素宽高比
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DisplayGeometry {
+    // 显示矩阵要求的顺时针旋转角度，只取 0、90、180、270
+    pub rotation: u32,
+    // 容器里记录的像素宽高比，解码出的帧没有带时使用
+    pub sample_aspect_ratio: Option<ffmpeg::Rational>,
+}
+
+impl DisplayGeometry {
+    pub(crate) fn new(stream: &ffmpeg::format::stream::Stream) -> Self {
+        // 手机拍摄的视频把旋转写在显示矩阵里，ffmpeg-next 没有包装
+        let (rotation, sample_aspect_ratio) = unsafe {
+            let rotation = match display_matrix(stream) {
+                Some(matrix) => ffmpeg::ffi::av_display_rotation_get(matrix),
+                None => 0.0,
+            };
+            let parameters = &*stream.parameters().as_ptr();
+            (rotation, ffmpeg::Rational::from(parameters.sample_aspect_ratio))
+        };
+        // av_display_rotation_get 返回逆时针角度
+        let rotation = if rotation.is_finite() { ((-rotation / 90.0).round() as i64).rem_euclid(4) as u32 * 90 } else { 0 };
+        let sample_aspect_ratio = (sample_aspect_ratio.numerator() > 0 && sample_aspect_ratio.denominator() > 0)
+            .then_some(sample_aspect_ratio);
+        Self { rotation, sample_aspect_ratio }
+    }
+
+    // 把画面转正的滤镜，不需要旋转时为 None
+    fn rotation_filter(&self) -> Option<&'static str> {
+        match self.rotation {
+            90 => Some("transpose=clock"),
+            180 => Some("hflip,vflip"),
+            270 => Some("transpose=cclock"),
+            _ => None,
+        }
+    }
+}
+
+// 显示矩阵是 9 个 i32
+const DISPLAY_MATRIX_BYTES: usize = 9 * std::mem::size_of::<i32>();
+
+// FFmpeg 6.1 起显示矩阵在编码参数的 coded_side_data 里
+#[cfg(ffmpeg_6_1)]
+unsafe fn display_matrix(stream: &ffmpeg::format::stream::Stream) -> Option<*const i32> {
+    let parameters = &*stream.parameters().as_ptr();
+    let side_data = ffmpeg::ffi::av_packet_side_data_get(
+        parameters.coded_side_data,
+        parameters.nb_coded_side_data,
+        ffmpeg::ffi::AVPacketSideDataType::AV_PKT_DATA_DISPLAYMATRIX,
+    );
+    if side_data.is_null() || (*side_data).size < DISPLAY_MATRIX_BYTES {
+        return None;
+    }
+    Some((*side_data).data as *const i32)
+}
+
+// 更早的版本在 AVStream 的 side_data 里；size 在 FFmpeg 5.0 之前是 int
+#[cfg(not(ffmpeg_6_1))]
+#[allow(clippy::unnecessary_cast)]
+unsafe fn display_matrix(stream: &ffmpeg::format::stream::Stream) -> Option<*const i32> {
+    let stream = &*stream.as_ptr();
+    if stream.side_data.is_null() {
+        return None;
+    }
+    std::slice::from_raw_parts(stream.side_data, stream.nb_side_data.max(0) as usize)
+        .iter()
+        .find(|side_data| {
+            side_data.type_ == ffmpeg::ffi::AVPacketSideDataType::AV_PKT_DATA_DISPLAYMATRIX
+                && side_data.size as usize >= DISPLAY_MATRIX_BYTES
+        })
+        .map(|side_data| side_data.data as *const i32)
+}
+
+// 视频滤镜设置，Player 写入，视频线程在每一帧前检查版本号决定是否重建滤镜图
+#[derive(Default)]
+pub struct VideoFilterSettings {
+    filters: Mutex<VideoFilters>,
+    generation: AtomicU64,
+    // 按显示矩阵自动旋转画面
+    autorotate: bool,
+}
+
+#[derive(Clone, Default)]
+struct VideoFilters {
+    // libavfilter 滤镜描述，如 "crop=1280:720,hflip"
+    description: String,
+    // 在其它滤镜之前加 yadif，只处理标记为隔行的帧
+    deinterlace: bool,
+}
+
+impl VideoFilterSettings {
+    pub fn new(description: &str, deinterlace: bool, autorotate: bool) -> Self {
+        Self {
+            filters: Mutex::new(VideoFilters { description: description.trim().to_string(), deinterlace }),
+            generation: AtomicU64::new(0),
+            autorotate,
+        }
+    }
+
+    pub fn set_description(&self, description: &str) {
+        self.filters.lock().unwrap().description = description.trim().to_string();
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn description(&self) -> String {
+        self.filters.lock().unwrap().description.clone()
+    }
+
+    pub fn set_deinterlace(&self, deinterlace: bool) {
+        self.filters.lock().unwrap().deinterlace = deinterlace;
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn deinterlace(&self) -> bool {
+        self.filters.lock().unwrap().deinterlace
+    }
+
+    // 实际交给 libavfilter 的完整滤镜链，空字符串表示不经过滤镜。
+    // 去隔行要在旋转之前做，用户的滤镜作用在转正后的画面上
+    fn graph_description(&self, geometry: &DisplayGeometry) -> String {
+        let filters = self.filters.lock().unwrap();
+        let mut chain = Vec::new();
+        if filters.deinterlace {
+            chain.push("yadif=deint=interlaced");
+        }
+        if let Some(rotation_filter) = geometry.rotation_filter().filter(|_| self.autorotate) {
+            chain.push(rotation_filter);
+        }
+        if !filters.description.is_empty() {
+            chain.push(&filters.description);
+        }
+        chain.join(",")
+    }
+}
+
+// 建好的视频滤镜图
+struct VideoFilter {
+    graph: ffmpeg::filter::Graph,
+    // 建图时的输入帧格式和大小，变化后需要重建
+    input: (ffmpeg::format::Pixel, u32, u32),
+    // 输出帧时间戳的时间基秒数，yadif 按场输出等滤镜会改变时间基
+    output_time_base: f64,
+}
+
+impl VideoFilter {
+    fn new(
+        description: &str,
+        frame: &ffmpeg::util::frame::Video,
+        time_base: ffmpeg::Rational,
+    ) -> Result<Self, ffmpeg::Error> {
+        let mut graph = ffmpeg::filter::Graph::new();
+        let aspect = frame.aspect_ratio();
+        let aspect = if aspect.numerator() > 0 && aspect.denominator() > 0 { aspect } else { ffmpeg::Rational::new(1, 1) };
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect={}/{}",
+            frame.width(),
+            frame.height(),
+            ffmpeg::ffi::AVPixelFormat::from(frame.format()) as i32,
+            time_base.numerator(),
+            time_base.denominator(),
+            aspect.numerator(),
+            aspect.denominator()
+        );
+        let buffer = ffmpeg::filter::find("buffer").ok_or(ffmpeg::Error::FilterNotFound)?;
+        let buffersink = ffmpeg::filter::find("buffersink").ok_or(ffmpeg::Error::FilterNotFound)?;
+        graph.add(&buffer, "in", &args)?;
+        graph.add(&buffersink, "out", "")?;
+        graph.output("in", 0)?.input("out", 0)?.parse(description)?;
+        graph.validate()?;
+        let output_time_base = match graph.get("out") {
+            Some(output) => f64::from(ffmpeg::Rational::from(unsafe {
+                ffmpeg::ffi::av_buffersink_get_time_base(output.as_ptr())
+            })),
+            None => return Err(ffmpeg::Error::FilterNotFound),
+        };
+        Ok(Self { graph, input: (frame.format(), frame.width(), frame.height()), output_time_base })
+    }
+
+    fn push(&mut self, frame: &ffmpeg::util::frame::Video) -> Result<(), ffmpeg::Error> {
+        match self.graph.get("in") {
+            Some(mut input) => input.source().add(frame),
+            None => Err(ffmpeg::Error::FilterNotFound),
+        }
+    }
+
+    // 通知滤镜已经没有输入，缓存的帧可以全部取出
+    fn flush(&mut self) -> Result<(), ffmpeg::Error> {
+        match self.graph.get("in") {
+            Some(mut input) => input.source().flush(),
+            None => Err(ffmpeg::Error::FilterNotFound),
+        }
+    }
+
+    // 取出所有已经处理好的帧，附带换算成秒的时间
+    fn pull(&mut self, frames: &mut Vec<(ffmpeg::util::frame::Video, Option<f64>)>) {
+        let Some(mut output) = self.graph.get("out") else {
+            return;
+        };
+        let mut filtered_frame = ffmpeg::util::frame::Video::empty();
+        while output.sink().frame(&mut filtered_frame).is_ok() {
+            let frame_time = filtered_frame.pts().map(|pts| pts as f64 * self.output_time_base);
+            frames.push((std::mem::replace(&mut filtered_frame, ffmpeg::util::frame::Video::empty()), frame_time));
+        }
+    }
+}
+
+// 解码和视频回调之间的滤镜阶段，设置、输入格式或时间基变化时重建滤镜图
+struct FilterStage {
+    settings: Arc<VideoFilterSettings>,
+    geometry: DisplayGeometry,
+    // filter 对应的设置版本
+    generation: Option<u64>,
+    filter: Option<VideoFilter>,
+    // 滤镜图已经冲刷或清空，下一帧到来时重建
+    stale: bool,
+}
+
+impl FilterStage {
+    fn new(settings: Arc<VideoFilterSettings>, geometry: DisplayGeometry) -> Self {
+        Self { settings, geometry, generation: None, filter: None, stale: false }
+    }
+
+    // 跳转或换文件后丢掉滤镜里缓存的帧
+    fn reset(&mut self) {
+        self.stale = true;
+    }
+
+    // 换到下一个文件时旋转方向和像素宽高比可能不同
+    fn set_geometry(&mut self, geometry: DisplayGeometry) {
+        self.geometry = geometry;
+        self.stale = true;
+    }
+
+    // 把一帧送进滤镜，返回可以显示的帧；没有滤镜时原样返回
+    fn process(
+        &mut self,
+        mut frame: ffmpeg::util::frame::Video,
+        clock: &StreamClock,
+        error_callback: &ErrorCallback,
+    ) -> Vec<(ffmpeg::util::frame::Video, Option<f64>)> {
+        // 和 av_guess_sample_aspect_ratio 一样，帧里没有像素宽高比时用容器里的
+        let frame_aspect = frame.aspect_ratio();
+        if frame_aspect.numerator() <= 0 || frame_aspect.denominator() <= 0 {
+            if let Some(sample_aspect_ratio) = self.geometry.sample_aspect_ratio {
+                unsafe {
+                    (*frame.as_mut_ptr()).sample_aspect_ratio = sample_aspect_ratio.into();
+                }
+            }
+        }
+
+        let generation = self.settings.generation.load(Ordering::Relaxed);
+        let input = (frame.format(), frame.width(), frame.height());
+        if self.generation != Some(generation)
+            || self.stale
+            || self.filter.as_ref().is_some_and(|filter| filter.input != input)
+        {
+            self.generation = Some(generation);
+            self.stale = false;
+            self.filter = None;
+            let description = self.settings.graph_description(&self.geometry);
+            if !description.is_empty() {
+                match VideoFilter::new(&description, &frame, clock.time_base) {
+                    Ok(filter) => {
+                        println!("视频滤镜: {} ({:?} {}x{})", description, input.0, input.1, input.2);
+                        self.filter = Some(filter);
+                    }
+                    Err(e) => {
+                        // 设置再次变化之前不经过滤镜直接显示
+                        println!("创建视频滤镜失败: {}", e);
+                        error_callback(PlayerError::Filter { description, source: e });
+                    }
+                }
+            }
+        }
+
+        let mut frames = Vec::new();
+        let Some(filter) = &mut self.filter else {
+            let frame_time = frame.timestamp().map(|pts| clock.pts_to_seconds(pts));
+            frames.push((frame, frame_time));
+            return frames;
+        };
+        // 和 ffplay 一样用 best_effort_timestamp 作为送进滤镜的时间戳，和不经过滤镜时一致
+        frame.set_pts(frame.timestamp());
+        if let Err(e) = filter.push(&frame) {
+            println!("视频滤镜处理失败: {}", e);
+            error_callback(PlayerError::Filter { description: self.settings.graph_description(&self.geometry), source: e });
+            self.filter = None;
+            return frames;
+        }
+        filter.pull(&mut frames);
+        frames
+    }
+
+    // 文件读完时取出滤镜里剩余的帧，之后的帧用重建的滤镜图处理
+    fn drain(&mut self) -> Vec<(ffmpeg::util::frame::Video, Option<f64>)> {
+        let mut frames = Vec::new();
+        if let (Some(filter), false) = (&mut self.filter, self.stale) {
+            if filter.flush().is_ok() {
+                filter.pull(&mut frames);
+            }
+        }
+        self.stale = true;
+        frames
+    }
+}
+
+// 把流时间基下的时间戳换算成秒
 struct StreamClock {
+    // 原样交给滤镜图的 buffer 源，避免经过浮点数换算后失真
+    time_base: ffmpeg::Rational,
     time_base_seconds: f64,
-    start_time: std::time::Instant,
 }
 
 impl StreamClock {
     fn new(stream: &ffmpeg::format::stream::Stream) -> Self {
-        let time_base_seconds = stream.time_base();
-        let time_base_seconds =
-            time_base_seconds.numerator() as f64 / time_base_seconds.denominator() as f64;
+        Self::from_time_base(stream.time_base())
+    }
 
-        let start_time = std::time::Instant::now();
+    fn from_time_base(time_base: ffmpeg::Rational) -> Self {
+        let time_base_seconds = time_base.numerator() as f64 / time_base.denominator() as f64;
 
-        Self { time_base_seconds, start_time }
+        Self { time_base, time_base_seconds }
     }
 
-    fn convert_pts_to_instant(&self, pts: Option<i64>) -> Option<std::time::Duration> {
-        pts.and_then(|pts| {
-            let pts_since_start =
-                std::time::Duration::from_secs_f64(pts as f64 * self.time_base_seconds);
-            self.start_time.checked_add(pts_since_start)
-        })
-        .map(|absolute_pts| absolute_pts.duration_since(std::time::Instant::now()))
+    fn pts_to_seconds(&self, pts: i64) -> f64 {
+        pts as f64 * self.time_base_seconds
+    }
+
+    fn seconds_to_pts(&self, seconds: f64) -> i64 {
+        (seconds / self.time_base_seconds) as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_timing_against_master_clock() {
+        let policy = FrameDropPolicy::default();
+        assert_eq!(policy.frame_timing(5.0, 5.0, 1.0), FrameTiming::Show);
+        // 落后不到阈值照常显示
+        assert_eq!(policy.frame_timing(5.0, 5.05, 1.0), FrameTiming::Show);
+        assert_eq!(policy.frame_timing(5.0, 5.2, 1.0), FrameTiming::Late);
+        // 超前时等待，倍速播放时等待时间按倍速缩短，每次最多等 MAX_FRAME_WAIT
+        assert_eq!(policy.frame_timing(5.04, 5.0, 2.0), FrameTiming::Wait((5.04 - 5.0) / 2.0));
+        assert_eq!(policy.frame_timing(6.0, 5.0, 1.0), FrameTiming::Wait(MAX_FRAME_WAIT));
+        // 相差太多时不等也不丢
+        assert_eq!(policy.frame_timing(0.0, 20.0, 1.0), FrameTiming::Show);
+        assert_eq!(policy.frame_timing(20.0, 0.0, 1.0), FrameTiming::Show);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn late_frames_are_dropped_unless_video_is_master() {
+        let policy = FrameDropPolicy::default();
+        assert!(policy.should_drop(true, SyncMode::Audio));
+        assert!(policy.should_drop(true, SyncMode::External));
+        assert!(!policy.should_drop(true, SyncMode::Video));
+        assert!(!policy.should_drop(false, SyncMode::Audio));
+
+        let disabled = FrameDropPolicy { enabled: false, ..FrameDropPolicy::default() };
+        assert!(!disabled.should_drop(true, SyncMode::Audio));
+    }
+
+    #[test]
+    fn decoder_skips_after_consecutive_drops() {
+        let policy = FrameDropPolicy::default();
+        assert!(!policy.should_skip_decoding(4));
+        assert!(policy.should_skip_decoding(5));
+
+        let never = FrameDropPolicy { decoder_skip_after: None, ..FrameDropPolicy::default() };
+        assert!(!never.should_skip_decoding(u32::MAX));
+    }
+
+    #[test]
+    fn stream_clock_keeps_exact_time_base() {
+        // 29.97fps 的 1001/30000 经过浮点数再换回来会失真，滤镜图要拿到原值
+        let clock = StreamClock::from_time_base(ffmpeg::Rational::new(1001, 30000));
+        assert_eq!((clock.time_base.numerator(), clock.time_base.denominator()), (1001, 30000));
+        assert!((clock.pts_to_seconds(30) - 1.001).abs() < 1e-9);
+    }
+
+    #[test]
+    fn stream_clock_round_trips_mpegts_time_base() {
+        let clock = StreamClock::from_time_base(ffmpeg::Rational::new(1, 90000));
+        assert!((clock.pts_to_seconds(900_000) - 10.0).abs() < 1e-9);
+        assert_eq!(clock.seconds_to_pts(10.0), 900_000);
+        assert_eq!(clock.pts_to_seconds(0), 0.0);
+        for pts in [3003, 450_450, 8_100_000] {
+            let round_trip = clock.seconds_to_pts(clock.pts_to_seconds(pts));
+            assert!((round_trip - pts).abs() <= 1, "{} -> {}", pts, round_trip);
+        }
+    }
+}